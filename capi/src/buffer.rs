@@ -0,0 +1,156 @@
+use std::io::{Read, Write};
+use std::os::raw::{c_char, c_int, c_uint};
+
+use libribzip2::read::BzDecoder;
+
+use crate::compress::{encoder, valid_parameters};
+use crate::decompress::decode_options;
+use crate::{decode_error_code, BZ_OK, BZ_OUTBUFF_FULL, BZ_PARAM_ERROR};
+
+/// Compress `sourceLen` bytes from `source` into `dest`. On entry `destLen` holds the size of
+/// `dest`, on success the size of the compressed data.
+///
+/// # Safety
+///
+/// `dest` must be valid for `*destLen` bytes and `source` for `sourceLen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzBuffToBuffCompress(
+    dest: *mut c_char,
+    destLen: *mut c_uint,
+    source: *mut c_char,
+    sourceLen: c_uint,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+) -> c_int {
+    if dest.is_null()
+        || destLen.is_null()
+        || source.is_null()
+        || !valid_parameters(blockSize100k, verbosity, workFactor)
+    {
+        return BZ_PARAM_ERROR;
+    }
+    let source = std::slice::from_raw_parts(source as *const u8, sourceLen as usize);
+    let mut encoder = encoder(blockSize100k);
+    if encoder.write_all(source).is_err() {
+        return BZ_PARAM_ERROR;
+    }
+    match encoder.finish() {
+        Ok(compressed) => copy_to_dest(&compressed, dest, destLen),
+        Err(_) => BZ_PARAM_ERROR,
+    }
+}
+
+/// Decompress the first stream in `source` into `dest`. On entry `destLen` holds the size of
+/// `dest`, on success the size of the decompressed data.
+///
+/// # Safety
+///
+/// `dest` must be valid for `*destLen` bytes and `source` for `sourceLen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzBuffToBuffDecompress(
+    dest: *mut c_char,
+    destLen: *mut c_uint,
+    source: *mut c_char,
+    sourceLen: c_uint,
+    small: c_int,
+    verbosity: c_int,
+) -> c_int {
+    if dest.is_null()
+        || destLen.is_null()
+        || source.is_null()
+        || !(0..=1).contains(&small)
+        || !(0..=4).contains(&verbosity)
+    {
+        return BZ_PARAM_ERROR;
+    }
+    let source = std::slice::from_raw_parts(source as *const u8, sourceLen as usize);
+    // reading one byte more than fits tells apart a full buffer from an exact fit
+    let mut decompressed = vec![];
+    let limit = u64::from(*destLen) + 1;
+    match BzDecoder::with_options(source, decode_options(small))
+        .take(limit)
+        .read_to_end(&mut decompressed)
+    {
+        Ok(_) => copy_to_dest(&decompressed, dest, destLen),
+        Err(error) => decode_error_code(&error),
+    }
+}
+
+unsafe fn copy_to_dest(data: &[u8], dest: *mut c_char, destLen: *mut c_uint) -> c_int {
+    if data.len() > *destLen as usize {
+        return BZ_OUTBUFF_FULL;
+    }
+    std::ptr::copy_nonoverlapping(data.as_ptr(), dest as *mut u8, data.len());
+    *destLen = data.len() as c_uint;
+    BZ_OK
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BZ_UNEXPECTED_EOF;
+
+    fn compress(data: &[u8], dest_len: usize) -> (c_int, Vec<u8>) {
+        let mut dest = vec![0u8; dest_len];
+        let mut dest_len = dest_len as c_uint;
+        let result = unsafe {
+            BZ2_bzBuffToBuffCompress(
+                dest.as_mut_ptr() as *mut _,
+                &mut dest_len,
+                data.as_ptr() as *mut _,
+                data.len() as c_uint,
+                9,
+                0,
+                30,
+            )
+        };
+        dest.truncate(dest_len as usize);
+        (result, dest)
+    }
+
+    fn decompress(data: &[u8], dest_len: usize, small: c_int) -> (c_int, Vec<u8>) {
+        let mut dest = vec![0u8; dest_len];
+        let mut dest_len = dest_len as c_uint;
+        let result = unsafe {
+            BZ2_bzBuffToBuffDecompress(
+                dest.as_mut_ptr() as *mut _,
+                &mut dest_len,
+                data.as_ptr() as *mut _,
+                data.len() as c_uint,
+                small,
+                0,
+            )
+        };
+        dest.truncate(dest_len as usize);
+        (result, dest)
+    }
+
+    #[test]
+    pub fn roundtrips() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(10);
+        let (result, compressed) = compress(&data, 1000);
+        assert_eq!(result, BZ_OK);
+        assert_eq!(&compressed[..4], b"BZh9");
+
+        for small in [0, 1] {
+            assert_eq!(
+                decompress(&compressed, data.len(), small),
+                (BZ_OK, data.clone())
+            );
+        }
+        assert_eq!(
+            decompress(&compressed, data.len() - 1, 0).0,
+            BZ_OUTBUFF_FULL
+        );
+        assert_eq!(
+            decompress(&compressed[..compressed.len() - 5], 1000, 0).0,
+            BZ_UNEXPECTED_EOF
+        );
+    }
+
+    #[test]
+    pub fn reports_full_output_buffer() {
+        assert_eq!(compress(b"too long", 10).0, BZ_OUTBUFF_FULL);
+    }
+}
@@ -0,0 +1,241 @@
+use std::io::Write;
+use std::os::raw::c_int;
+
+use libribzip2::stream::Bz2Encoder;
+use libribzip2::{EncodingStrategy, Level};
+
+use crate::{
+    bz_stream, BZ_FINISH, BZ_FINISH_OK, BZ_FLUSH, BZ_FLUSH_OK, BZ_OK, BZ_PARAM_ERROR, BZ_RUN,
+    BZ_RUN_OK, BZ_SEQUENCE_ERROR, BZ_STREAM_END, INPUT_CHUNK,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Running,
+    Flushing,
+    Finishing,
+    Idle,
+}
+
+/// Compressed output is collected in the encoder's vector until it fits into the output
+/// buffer of the stream.
+struct Compressor {
+    encoder: Bz2Encoder<Vec<u8>>,
+    /// Bytes at the start of the encoder's vector already copied to the output.
+    copied: usize,
+    mode: Mode,
+}
+
+/// Parameters as accepted by libbz2. The work factor only affects libbz2's sorting algorithm.
+pub(crate) fn valid_parameters(
+    block_size_100k: c_int,
+    verbosity: c_int,
+    work_factor: c_int,
+) -> bool {
+    (1..=9).contains(&block_size_100k)
+        && (0..=4).contains(&verbosity)
+        && (0..=250).contains(&work_factor)
+}
+
+pub(crate) fn encoder(block_size_100k: c_int) -> Bz2Encoder<Vec<u8>> {
+    let level = Level::new(block_size_100k as u8).unwrap();
+    Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, level)
+}
+
+/// # Safety
+///
+/// `strm` must point to a `bz_stream` which is not in use by another compressor or
+/// decompressor.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzCompressInit(
+    strm: *mut bz_stream,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+) -> c_int {
+    if strm.is_null() || !valid_parameters(blockSize100k, verbosity, workFactor) {
+        return BZ_PARAM_ERROR;
+    }
+    let stream = &mut *strm;
+    let compressor = Compressor {
+        encoder: encoder(blockSize100k),
+        copied: 0,
+        mode: Mode::Running,
+    };
+    stream.state = Box::into_raw(Box::new(compressor)).cast();
+    stream.total_in_lo32 = 0;
+    stream.total_in_hi32 = 0;
+    stream.total_out_lo32 = 0;
+    stream.total_out_hi32 = 0;
+    BZ_OK
+}
+
+/// # Safety
+///
+/// `strm` must have been initialized by [BZ2_bzCompressInit], its input and output pointers
+/// must be valid for `avail_in` and `avail_out` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzCompress(strm: *mut bz_stream, action: c_int) -> c_int {
+    if strm.is_null() || (*strm).state.is_null() {
+        return BZ_PARAM_ERROR;
+    }
+    let stream = &mut *strm;
+    let compressor = &mut *(stream.state as *mut Compressor);
+    let encoder = &mut compressor.encoder;
+
+    stream.produce_output(encoder.get_mut(), &mut compressor.copied);
+    let result = match (compressor.mode, action) {
+        (Mode::Running, BZ_RUN) => {
+            stream.consume_input(|input| write_input(encoder, input));
+            Ok(())
+        }
+        (Mode::Running, BZ_FLUSH) => {
+            stream.consume_input(|input| write_input(encoder, input));
+            if stream.avail_in > 0 {
+                Ok(())
+            } else {
+                compressor.mode = Mode::Flushing;
+                encoder.flush()
+            }
+        }
+        (Mode::Running, BZ_FINISH) => {
+            stream.consume_input(|input| write_input(encoder, input));
+            if stream.avail_in > 0 {
+                Ok(())
+            } else {
+                compressor.mode = Mode::Finishing;
+                encoder.try_finish()
+            }
+        }
+        (Mode::Flushing, BZ_FLUSH) | (Mode::Finishing, BZ_FINISH) => Ok(()),
+        (Mode::Running, _) => return BZ_PARAM_ERROR,
+        _ => return BZ_SEQUENCE_ERROR,
+    };
+    if result.is_err() {
+        return BZ_SEQUENCE_ERROR;
+    }
+    stream.produce_output(encoder.get_mut(), &mut compressor.copied);
+
+    let drained = encoder.get_ref().is_empty();
+    match compressor.mode {
+        Mode::Flushing if drained => {
+            compressor.mode = Mode::Running;
+            BZ_RUN_OK
+        }
+        Mode::Flushing => BZ_FLUSH_OK,
+        Mode::Finishing if drained => {
+            compressor.mode = Mode::Idle;
+            BZ_STREAM_END
+        }
+        Mode::Finishing => BZ_FINISH_OK,
+        // the input is not yet consumed completely
+        _ if action == BZ_FLUSH => BZ_FLUSH_OK,
+        _ if action == BZ_FINISH => BZ_FINISH_OK,
+        _ => BZ_RUN_OK,
+    }
+}
+
+/// # Safety
+///
+/// `strm` must have been initialized by [BZ2_bzCompressInit].
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzCompressEnd(strm: *mut bz_stream) -> c_int {
+    if strm.is_null() || (*strm).state.is_null() {
+        return BZ_PARAM_ERROR;
+    }
+    drop(Box::from_raw((*strm).state as *mut Compressor));
+    (*strm).state = std::ptr::null_mut();
+    BZ_OK
+}
+
+/// Write input until compressed output is pending, returns how much was written.
+fn write_input(encoder: &mut Bz2Encoder<Vec<u8>>, input: &[u8]) -> usize {
+    let mut written = 0;
+    for chunk in input.chunks(INPUT_CHUNK) {
+        if !encoder.get_ref().is_empty() || encoder.write_all(chunk).is_err() {
+            break;
+        }
+        written += chunk.len();
+    }
+    written
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{decompress, new_stream, random_data};
+
+    #[test]
+    pub fn compresses_with_small_output_buffer() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(100);
+        let mut stream = new_stream();
+        unsafe {
+            assert_eq!(BZ2_bzCompressInit(&mut stream, 5, 0, 0), BZ_OK);
+            stream.next_in = data.as_ptr() as *mut _;
+            stream.avail_in = data.len() as u32;
+            assert_eq!(BZ2_bzCompress(&mut stream, BZ_RUN), BZ_RUN_OK);
+            assert_eq!(stream.avail_in, 0);
+
+            let mut compressed = vec![];
+            let mut buffer = [0u8; 16];
+            loop {
+                stream.next_out = buffer.as_mut_ptr() as *mut _;
+                stream.avail_out = buffer.len() as u32;
+                let result = BZ2_bzCompress(&mut stream, BZ_FINISH);
+                compressed.extend_from_slice(&buffer[..16 - stream.avail_out as usize]);
+                if result == BZ_STREAM_END {
+                    break;
+                }
+                assert_eq!(result, BZ_FINISH_OK);
+            }
+            assert_eq!(BZ2_bzCompress(&mut stream, BZ_RUN), BZ_SEQUENCE_ERROR);
+            assert_eq!(stream.total_in_lo32, data.len() as u32);
+            assert_eq!(stream.total_out_lo32, compressed.len() as u32);
+            assert_eq!(BZ2_bzCompressEnd(&mut stream), BZ_OK);
+
+            assert_eq!(&compressed[..4], b"BZh5");
+            assert_eq!(decompress(&compressed), data);
+        }
+    }
+
+    #[test]
+    pub fn takes_no_input_while_output_is_pending() {
+        let data = random_data(500_000);
+        let mut stream = new_stream();
+        let mut compressed = vec![];
+        let mut byte = 0u8;
+        unsafe {
+            assert_eq!(BZ2_bzCompressInit(&mut stream, 1, 0, 0), BZ_OK);
+            stream.next_in = data.as_ptr() as *mut _;
+            stream.avail_in = data.len() as u32;
+            loop {
+                stream.next_out = &mut byte as *mut u8 as *mut _;
+                stream.avail_out = 1;
+                let result = BZ2_bzCompress(&mut stream, BZ_FINISH);
+                compressed.extend_from_slice(&[byte][..1 - stream.avail_out as usize]);
+                // the last block and the one finishing the stream
+                let compressor = &*(stream.state as *const Compressor);
+                assert!(compressor.encoder.get_ref().len() < 2 * 110_000);
+                if result == BZ_STREAM_END {
+                    break;
+                }
+                assert_eq!(result, BZ_FINISH_OK);
+                if compressed.len() == 1 {
+                    assert!(stream.avail_in > 0);
+                }
+            }
+            assert_eq!(BZ2_bzCompressEnd(&mut stream), BZ_OK);
+        }
+        assert_eq!(decompress(&compressed), data);
+    }
+
+    #[test]
+    pub fn rejects_invalid_parameters() {
+        let mut stream = new_stream();
+        unsafe {
+            assert_eq!(BZ2_bzCompressInit(&mut stream, 0, 0, 0), BZ_PARAM_ERROR);
+            assert_eq!(BZ2_bzCompressInit(&mut stream, 9, 0, 251), BZ_PARAM_ERROR);
+            assert_eq!(BZ2_bzCompress(&mut stream, BZ_RUN), BZ_PARAM_ERROR);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::Write;
+use std::os::raw::c_int;
+
+use libribzip2::stream::DecodeOptions;
+use libribzip2::write::BzDecoder;
+
+use crate::{bz_stream, decode_error_code, BZ_OK, BZ_PARAM_ERROR, BZ_STREAM_END, INPUT_CHUNK};
+
+/// Decompressed output is collected in the decoder's vector until it fits into the output
+/// buffer of the stream. Errors are returned again by later calls.
+struct Decompressor {
+    decoder: BzDecoder<Vec<u8>>,
+    /// Bytes at the start of the decoder's vector already copied to the output.
+    copied: usize,
+    error: Option<c_int>,
+}
+
+/// Like libbz2, `small` trades speed for less memory, see [DecodeOptions::small_memory].
+pub(crate) fn decode_options(small: c_int) -> DecodeOptions {
+    let mut options = DecodeOptions::default();
+    options.small_memory = small == 1;
+    options
+}
+
+/// # Safety
+///
+/// `strm` must point to a `bz_stream` which is not in use by another compressor or
+/// decompressor.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzDecompressInit(
+    strm: *mut bz_stream,
+    verbosity: c_int,
+    small: c_int,
+) -> c_int {
+    if strm.is_null() || !(0..=4).contains(&verbosity) || !(0..=1).contains(&small) {
+        return BZ_PARAM_ERROR;
+    }
+    let stream = &mut *strm;
+    let decompressor = Decompressor {
+        decoder: BzDecoder::with_options(vec![], decode_options(small)),
+        copied: 0,
+        error: None,
+    };
+    stream.state = Box::into_raw(Box::new(decompressor)).cast();
+    stream.total_in_lo32 = 0;
+    stream.total_in_hi32 = 0;
+    stream.total_out_lo32 = 0;
+    stream.total_out_hi32 = 0;
+    BZ_OK
+}
+
+/// Consumes input up to the end of the stream, but none while decoded output is still waiting
+/// for room in the output buffer. Returns [BZ_STREAM_END] once all of the stream has been
+/// decoded and written to the output.
+///
+/// # Safety
+///
+/// `strm` must have been initialized by [BZ2_bzDecompressInit], its input and output pointers
+/// must be valid for `avail_in` and `avail_out` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzDecompress(strm: *mut bz_stream) -> c_int {
+    if strm.is_null() || (*strm).state.is_null() {
+        return BZ_PARAM_ERROR;
+    }
+    let stream = &mut *strm;
+    let decompressor = &mut *(stream.state as *mut Decompressor);
+    if let Some(error) = decompressor.error {
+        return error;
+    }
+    let decoder = &mut decompressor.decoder;
+
+    stream.produce_output(decoder.get_mut(), &mut decompressor.copied);
+    let mut error = None;
+    stream.consume_input(|input| {
+        // decode further only once the output of the previous blocks has been taken
+        let mut consumed = 0;
+        for chunk in input.chunks(INPUT_CHUNK) {
+            if !decoder.get_ref().is_empty() {
+                break;
+            }
+            match decoder.write(chunk) {
+                Ok(amount) => {
+                    consumed += amount;
+                    // the stream ended within the chunk
+                    if amount < chunk.len() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error = Some(decode_error_code(&e));
+                    break;
+                }
+            }
+        }
+        consumed
+    });
+    if let Some(error) = error {
+        decompressor.error = Some(error);
+        return error;
+    }
+    stream.produce_output(decoder.get_mut(), &mut decompressor.copied);
+
+    if decoder.is_finished() && decoder.get_ref().is_empty() {
+        BZ_STREAM_END
+    } else {
+        BZ_OK
+    }
+}
+
+/// # Safety
+///
+/// `strm` must have been initialized by [BZ2_bzDecompressInit].
+#[no_mangle]
+pub unsafe extern "C" fn BZ2_bzDecompressEnd(strm: *mut bz_stream) -> c_int {
+    if strm.is_null() || (*strm).state.is_null() {
+        return BZ_PARAM_ERROR;
+    }
+    drop(Box::from_raw((*strm).state as *mut Decompressor));
+    (*strm).state = std::ptr::null_mut();
+    BZ_OK
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{compress, new_stream, random_data};
+    use crate::{BZ_DATA_ERROR, BZ_DATA_ERROR_MAGIC};
+
+    #[test]
+    pub fn decompresses_in_small_steps() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(100);
+        let mut compressed = compress(&data);
+        let len = compressed.len();
+        compressed.extend_from_slice(b"trailer");
+
+        for small in [0, 1] {
+            let mut stream = new_stream();
+            let mut decompressed = vec![];
+            let mut buffer = [0u8; 100];
+            unsafe {
+                assert_eq!(BZ2_bzDecompressInit(&mut stream, 0, small), BZ_OK);
+                stream.next_in = compressed.as_mut_ptr() as *mut _;
+                let mut remaining = compressed.len() as u32;
+                loop {
+                    // hand out the input in pieces of 10 bytes
+                    let step = remaining.min(10);
+                    stream.avail_in = step;
+                    stream.next_out = buffer.as_mut_ptr() as *mut _;
+                    stream.avail_out = buffer.len() as u32;
+                    let result = BZ2_bzDecompress(&mut stream);
+                    remaining -= step - stream.avail_in;
+                    decompressed.extend_from_slice(&buffer[..100 - stream.avail_out as usize]);
+                    if result == BZ_STREAM_END {
+                        break;
+                    }
+                    assert_eq!(result, BZ_OK);
+                }
+                assert_eq!(stream.total_in_lo32, len as u32);
+                assert_eq!(remaining, 7);
+                assert_eq!(BZ2_bzDecompressEnd(&mut stream), BZ_OK);
+            }
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    pub fn takes_no_input_while_output_is_pending() {
+        let data = random_data(500_000);
+        let mut encoder = crate::compress::encoder(1);
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut stream = new_stream();
+        let mut decompressed = vec![];
+        let mut byte = 0u8;
+        unsafe {
+            assert_eq!(BZ2_bzDecompressInit(&mut stream, 0, 0), BZ_OK);
+            stream.next_in = compressed.as_ptr() as *mut _;
+            stream.avail_in = compressed.len() as u32;
+            loop {
+                stream.next_out = &mut byte as *mut u8 as *mut _;
+                stream.avail_out = 1;
+                let result = BZ2_bzDecompress(&mut stream);
+                decompressed.extend_from_slice(&[byte][..1 - stream.avail_out as usize]);
+                // at most one block of 100k is decoded ahead of the output
+                let decompressor = &*(stream.state as *const Decompressor);
+                assert!(decompressor.decoder.get_ref().len() <= 100_000);
+                if result == BZ_STREAM_END {
+                    break;
+                }
+                assert_eq!(result, BZ_OK);
+                if decompressed.len() == 1 {
+                    assert!(stream.avail_in > 0);
+                }
+            }
+            assert_eq!(BZ2_bzDecompressEnd(&mut stream), BZ_OK);
+        }
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    pub fn reports_data_errors() {
+        let mut compressed = compress(b"corrupted");
+        compressed[10] ^= 1;
+        for (input, expected) in [
+            (&b"PK\x03\x04"[..], BZ_DATA_ERROR_MAGIC),
+            (&compressed[..], BZ_DATA_ERROR),
+        ] {
+            let mut stream = new_stream();
+            let mut buffer = [0u8; 100];
+            unsafe {
+                assert_eq!(BZ2_bzDecompressInit(&mut stream, 0, 0), BZ_OK);
+                stream.next_in = input.as_ptr() as *mut _;
+                stream.avail_in = input.len() as u32;
+                stream.next_out = buffer.as_mut_ptr() as *mut _;
+                stream.avail_out = buffer.len() as u32;
+                assert_eq!(BZ2_bzDecompress(&mut stream), expected);
+                assert_eq!(BZ2_bzDecompress(&mut stream), expected);
+                assert_eq!(BZ2_bzDecompressEnd(&mut stream), BZ_OK);
+            }
+        }
+    }
+}
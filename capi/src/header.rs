@@ -0,0 +1,167 @@
+//! Generates `include/bzlib.h` from the constants and functions exported by this crate.
+//!
+//! The constants are taken from the crate, the declarations of `bz_stream` and of the functions
+//! are maintained by hand. The type of each exported function is checked against its declaration
+//! when compiling. The checked in header is compared with the generated one by a test. Run the
+//! tests with `RIBZIP2_UPDATE_HEADER=1` to regenerate it.
+
+use std::os::raw::{c_char, c_int, c_uint};
+
+use crate::*;
+
+const ACTIONS: [(&str, c_int); 3] = [
+    ("BZ_RUN", BZ_RUN),
+    ("BZ_FLUSH", BZ_FLUSH),
+    ("BZ_FINISH", BZ_FINISH),
+];
+
+const RETURN_CODES: [(&str, c_int); 15] = [
+    ("BZ_OK", BZ_OK),
+    ("BZ_RUN_OK", BZ_RUN_OK),
+    ("BZ_FLUSH_OK", BZ_FLUSH_OK),
+    ("BZ_FINISH_OK", BZ_FINISH_OK),
+    ("BZ_STREAM_END", BZ_STREAM_END),
+    ("BZ_SEQUENCE_ERROR", BZ_SEQUENCE_ERROR),
+    ("BZ_PARAM_ERROR", BZ_PARAM_ERROR),
+    ("BZ_MEM_ERROR", BZ_MEM_ERROR),
+    ("BZ_DATA_ERROR", BZ_DATA_ERROR),
+    ("BZ_DATA_ERROR_MAGIC", BZ_DATA_ERROR_MAGIC),
+    ("BZ_IO_ERROR", BZ_IO_ERROR),
+    ("BZ_UNEXPECTED_EOF", BZ_UNEXPECTED_EOF),
+    ("BZ_OUTBUFF_FULL", BZ_OUTBUFF_FULL),
+    ("BZ_CONFIG_ERROR", BZ_CONFIG_ERROR),
+    ("BZ_MAX_UNUSED", 5000),
+];
+
+const BZ_STREAM: &str = "typedef struct {
+    char *next_in;
+    unsigned int avail_in;
+    unsigned int total_in_lo32;
+    unsigned int total_in_hi32;
+
+    char *next_out;
+    unsigned int avail_out;
+    unsigned int total_out_lo32;
+    unsigned int total_out_hi32;
+
+    void *state;
+
+    void *(*bzalloc)(void *, int, int);
+    void (*bzfree)(void *, void *);
+    void *opaque;
+} bz_stream;
+";
+
+/// Return type, name and parameters of the exported functions.
+const FUNCTIONS: [(&str, &str, &[&str]); 9] = [
+    (
+        "int",
+        "BZ2_bzCompressInit",
+        &[
+            "bz_stream *strm",
+            "int blockSize100k",
+            "int verbosity",
+            "int workFactor",
+        ],
+    ),
+    ("int", "BZ2_bzCompress", &["bz_stream *strm", "int action"]),
+    ("int", "BZ2_bzCompressEnd", &["bz_stream *strm"]),
+    (
+        "int",
+        "BZ2_bzDecompressInit",
+        &["bz_stream *strm", "int verbosity", "int small"],
+    ),
+    ("int", "BZ2_bzDecompress", &["bz_stream *strm"]),
+    ("int", "BZ2_bzDecompressEnd", &["bz_stream *strm"]),
+    (
+        "int",
+        "BZ2_bzBuffToBuffCompress",
+        &[
+            "char *dest",
+            "unsigned int *destLen",
+            "char *source",
+            "unsigned int sourceLen",
+            "int blockSize100k",
+            "int verbosity",
+            "int workFactor",
+        ],
+    ),
+    (
+        "int",
+        "BZ2_bzBuffToBuffDecompress",
+        &[
+            "char *dest",
+            "unsigned int *destLen",
+            "char *source",
+            "unsigned int sourceLen",
+            "int small",
+            "int verbosity",
+        ],
+    ),
+    ("const char *", "BZ2_bzlibVersion", &["void"]),
+];
+
+// Fail to compile when an exported function no longer matches its declaration in FUNCTIONS.
+const _: unsafe extern "C" fn(*mut bz_stream, c_int, c_int, c_int) -> c_int = BZ2_bzCompressInit;
+const _: unsafe extern "C" fn(*mut bz_stream, c_int) -> c_int = BZ2_bzCompress;
+const _: unsafe extern "C" fn(*mut bz_stream) -> c_int = BZ2_bzCompressEnd;
+const _: unsafe extern "C" fn(*mut bz_stream, c_int, c_int) -> c_int = BZ2_bzDecompressInit;
+const _: unsafe extern "C" fn(*mut bz_stream) -> c_int = BZ2_bzDecompress;
+const _: unsafe extern "C" fn(*mut bz_stream) -> c_int = BZ2_bzDecompressEnd;
+const _: unsafe extern "C" fn(
+    *mut c_char,
+    *mut c_uint,
+    *mut c_char,
+    c_uint,
+    c_int,
+    c_int,
+    c_int,
+) -> c_int = BZ2_bzBuffToBuffCompress;
+const _: unsafe extern "C" fn(
+    *mut c_char,
+    *mut c_uint,
+    *mut c_char,
+    c_uint,
+    c_int,
+    c_int,
+) -> c_int = BZ2_bzBuffToBuffDecompress;
+const _: extern "C" fn() -> *const c_char = BZ2_bzlibVersion;
+
+/// Returns the content of `bzlib.h`.
+pub fn bzlib_h() -> String {
+    let mut out = String::new();
+    out.push_str("/* Generated by libribzip2-capi, do not edit. */\n\n");
+    out.push_str("#ifndef _BZLIB_H\n#define _BZLIB_H\n\n");
+    out.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    for (name, value) in ACTIONS.iter().chain(RETURN_CODES.iter()) {
+        out.push_str(&format!("#define {:<20} {}\n", name, value));
+    }
+    out.push('\n');
+    out.push_str(BZ_STREAM);
+    for (return_type, name, parameters) in FUNCTIONS {
+        let separator = if return_type.ends_with('*') { "" } else { " " };
+        out.push_str(&format!(
+            "\nextern {}{}{}(\n    {}\n);\n",
+            return_type,
+            separator,
+            name,
+            parameters.join(",\n    ")
+        ));
+    }
+    out.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn header_is_up_to_date() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/include/bzlib.h");
+        if std::env::var_os("RIBZIP2_UPDATE_HEADER").is_some() {
+            std::fs::write(path, bzlib_h()).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(path).unwrap(), bzlib_h());
+    }
+}
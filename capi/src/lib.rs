@@ -0,0 +1,190 @@
+//! C ABI compatible with libbz2, built as `libbz2.so` and `libbz2.a`.
+//!
+//! Exports the low-level stream interface (`BZ2_bzCompressInit`, `BZ2_bzCompress`,
+//! `BZ2_bzDecompress`, ...) and the one-shot helpers `BZ2_bzBuffToBuffCompress` and
+//! `BZ2_bzBuffToBuffDecompress`. The header `include/bzlib.h` is written by [header] from the
+//! crate's constants and hand-maintained declarations. The `FILE*` based high-level interface
+//! is not provided.
+#![allow(non_snake_case, non_camel_case_types)]
+
+mod buffer;
+mod compress;
+mod decompress;
+pub mod header;
+
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+pub use buffer::{BZ2_bzBuffToBuffCompress, BZ2_bzBuffToBuffDecompress};
+pub use compress::{BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit};
+pub use decompress::{BZ2_bzDecompress, BZ2_bzDecompressEnd, BZ2_bzDecompressInit};
+
+pub const BZ_RUN: c_int = 0;
+pub const BZ_FLUSH: c_int = 1;
+pub const BZ_FINISH: c_int = 2;
+
+pub const BZ_OK: c_int = 0;
+pub const BZ_RUN_OK: c_int = 1;
+pub const BZ_FLUSH_OK: c_int = 2;
+pub const BZ_FINISH_OK: c_int = 3;
+pub const BZ_STREAM_END: c_int = 4;
+pub const BZ_SEQUENCE_ERROR: c_int = -1;
+pub const BZ_PARAM_ERROR: c_int = -2;
+pub const BZ_MEM_ERROR: c_int = -3;
+pub const BZ_DATA_ERROR: c_int = -4;
+pub const BZ_DATA_ERROR_MAGIC: c_int = -5;
+pub const BZ_IO_ERROR: c_int = -6;
+pub const BZ_UNEXPECTED_EOF: c_int = -7;
+pub const BZ_OUTBUFF_FULL: c_int = -8;
+pub const BZ_CONFIG_ERROR: c_int = -9;
+
+/// Input is handed to the encoder or decoder in pieces of this size, and only while none of
+/// its output is pending, so that the output buffer bounds the memory used by a call.
+const INPUT_CHUNK: usize = 256;
+
+/// Layout of `bz_stream` in libbz2. The allocation callbacks are accepted but not used,
+/// `state` points to Rust owned compressor or decompressor state.
+#[repr(C)]
+pub struct bz_stream {
+    pub next_in: *mut c_char,
+    pub avail_in: c_uint,
+    pub total_in_lo32: c_uint,
+    pub total_in_hi32: c_uint,
+
+    pub next_out: *mut c_char,
+    pub avail_out: c_uint,
+    pub total_out_lo32: c_uint,
+    pub total_out_hi32: c_uint,
+
+    pub state: *mut c_void,
+
+    pub bzalloc: Option<unsafe extern "C" fn(*mut c_void, c_int, c_int) -> *mut c_void>,
+    pub bzfree: Option<unsafe extern "C" fn(*mut c_void, *mut c_void)>,
+    pub opaque: *mut c_void,
+}
+
+impl bz_stream {
+    /// Take up to `avail_in` bytes from the input, `consume` returns how many it used.
+    unsafe fn consume_input(&mut self, consume: impl FnOnce(&[u8]) -> usize) {
+        let input = if self.avail_in == 0 {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(self.next_in as *const u8, self.avail_in as usize)
+        };
+        let amount = consume(input);
+        self.next_in = self.next_in.add(amount);
+        self.avail_in -= amount as c_uint;
+        let total_in = total(self.total_in_lo32, self.total_in_hi32) + amount as u64;
+        (self.total_in_lo32, self.total_in_hi32) = split(total_in);
+    }
+
+    /// Copy as much of `pending` after `copied` into the output as fits and advance `copied`.
+    /// `pending` is cleared once all of it has been copied.
+    unsafe fn produce_output(&mut self, pending: &mut Vec<u8>, copied: &mut usize) {
+        let amount = (pending.len() - *copied).min(self.avail_out as usize);
+        if amount == 0 {
+            return;
+        }
+        let source = pending[*copied..].as_ptr();
+        std::ptr::copy_nonoverlapping(source, self.next_out as *mut u8, amount);
+        *copied += amount;
+        if *copied == pending.len() {
+            pending.clear();
+            *copied = 0;
+        }
+        self.next_out = self.next_out.add(amount);
+        self.avail_out -= amount as c_uint;
+        let total_out = total(self.total_out_lo32, self.total_out_hi32) + amount as u64;
+        (self.total_out_lo32, self.total_out_hi32) = split(total_out);
+    }
+}
+
+fn total(lo32: c_uint, hi32: c_uint) -> u64 {
+    (u64::from(hi32) << 32) | u64::from(lo32)
+}
+
+fn split(total: u64) -> (c_uint, c_uint) {
+    (total as c_uint, (total >> 32) as c_uint)
+}
+
+/// Map decoding errors to the return codes of libbz2.
+fn decode_error_code(error: &std::io::Error) -> c_int {
+    match error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<libribzip2::Error>())
+    {
+        Some(libribzip2::Error::BadMagic) => BZ_DATA_ERROR_MAGIC,
+        Some(_) => BZ_DATA_ERROR,
+        None if error.kind() == std::io::ErrorKind::UnexpectedEof => BZ_UNEXPECTED_EOF,
+        None => BZ_IO_ERROR,
+    }
+}
+
+/// Returns the version string of the library, in the format of libbz2.
+#[no_mangle]
+pub extern "C" fn BZ2_bzlibVersion() -> *const c_char {
+    concat!("1.0.8, ribzip2 ", env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+
+    pub(crate) fn new_stream() -> bz_stream {
+        bz_stream {
+            next_in: std::ptr::null_mut(),
+            avail_in: 0,
+            total_in_lo32: 0,
+            total_in_hi32: 0,
+            next_out: std::ptr::null_mut(),
+            avail_out: 0,
+            total_out_lo32: 0,
+            total_out_hi32: 0,
+            state: std::ptr::null_mut(),
+            bzalloc: None,
+            bzfree: None,
+            opaque: std::ptr::null_mut(),
+        }
+    }
+
+    pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = libribzip2::write::BzEncoder::new(vec![], Default::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Xorshift output, which compresses to about its own size.
+    pub(crate) fn random_data(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    pub(crate) fn decompress(data: &[u8]) -> Vec<u8> {
+        let mut decompressed = vec![];
+        libribzip2::read::BzDecoder::new(data)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        decompressed
+    }
+
+    #[test]
+    pub fn splits_totals() {
+        assert_eq!(split(total(7, 1)), (7, 1));
+        assert_eq!(split(u64::from(u32::MAX) + 1), (0, 1));
+    }
+
+    #[test]
+    pub fn maps_errors() {
+        let error: std::io::Error = libribzip2::Error::BadMagic.into();
+        assert_eq!(decode_error_code(&error), BZ_DATA_ERROR_MAGIC);
+        let error: std::io::Error = std::io::ErrorKind::UnexpectedEof.into();
+        assert_eq!(decode_error_code(&error), BZ_UNEXPECTED_EOF);
+    }
+}
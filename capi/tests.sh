@@ -0,0 +1,15 @@
+#!/bin/bash
+# Builds the C test program against libribzip2-capi and checks interoperability with bzip2.
+set -eux
+
+cargo build
+mkdir -p temp
+gcc -Wall -I include tests/bzlib_test.c -L ../target/debug -lbz2 -Wl,-rpath,"$PWD/../target/debug" -o temp/bzlib_test
+temp/bzlib_test
+
+temp/bzlib_test -z < ../cli/samples/idiot.txt > temp/idiot.txt.bz2
+bunzip2 -c temp/idiot.txt.bz2 | cmp - ../cli/samples/idiot.txt
+
+bzip2 -c ../cli/samples/idiot.txt | temp/bzlib_test -d | cmp - ../cli/samples/idiot.txt
+
+rm -r temp
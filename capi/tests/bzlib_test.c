@@ -0,0 +1,122 @@
+/* Exercises the libbz2 API of libribzip2-capi, see tests.sh.
+ *
+ *   bzlib_test        run the self tests
+ *   bzlib_test -z     compress stdin to stdout using BZ2_bzCompress
+ *   bzlib_test -d     decompress stdin to stdout using BZ2_bzDecompress
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "bzlib.h"
+
+#define CHUNK 4096
+
+static int check(int condition, const char *message) {
+    if (!condition) {
+        fprintf(stderr, "bzlib_test: %s\n", message);
+        exit(1);
+    }
+    return condition;
+}
+
+static void compress_stdio(void) {
+    bz_stream strm;
+    char in[CHUNK], out[CHUNK];
+    int action = BZ_RUN, result;
+
+    memset(&strm, 0, sizeof(strm));
+    check(BZ2_bzCompressInit(&strm, 9, 0, 30) == BZ_OK, "BZ2_bzCompressInit failed");
+    do {
+        strm.avail_in = fread(in, 1, CHUNK, stdin);
+        strm.next_in = in;
+        if (feof(stdin)) {
+            action = BZ_FINISH;
+        }
+        do {
+            strm.next_out = out;
+            strm.avail_out = CHUNK;
+            result = BZ2_bzCompress(&strm, action);
+            check(result >= 0, "BZ2_bzCompress failed");
+            fwrite(out, 1, CHUNK - strm.avail_out, stdout);
+        } while (strm.avail_in > 0 || result == BZ_FINISH_OK);
+    } while (result != BZ_STREAM_END);
+    check(BZ2_bzCompressEnd(&strm) == BZ_OK, "BZ2_bzCompressEnd failed");
+}
+
+static void decompress_stdio(void) {
+    bz_stream strm;
+    char in[CHUNK], out[CHUNK];
+    int result = BZ_OK;
+
+    memset(&strm, 0, sizeof(strm));
+    check(BZ2_bzDecompressInit(&strm, 0, 0) == BZ_OK, "BZ2_bzDecompressInit failed");
+    while (result != BZ_STREAM_END) {
+        if (strm.avail_in == 0 && !feof(stdin)) {
+            strm.avail_in = fread(in, 1, CHUNK, stdin);
+            strm.next_in = in;
+        }
+        strm.next_out = out;
+        strm.avail_out = CHUNK;
+        result = BZ2_bzDecompress(&strm);
+        check(result == BZ_OK || result == BZ_STREAM_END, "BZ2_bzDecompress failed");
+        fwrite(out, 1, CHUNK - strm.avail_out, stdout);
+        /* no progress possible */
+        check(result == BZ_STREAM_END || strm.avail_out == 0 || strm.avail_in > 0 || !feof(stdin),
+              "unexpected end of input");
+    }
+    check(BZ2_bzDecompressEnd(&strm) == BZ_OK, "BZ2_bzDecompressEnd failed");
+}
+
+static void test_buff_to_buff(void) {
+    char source[10000], compressed[12000], decompressed[10000];
+    unsigned int compressed_len = sizeof(compressed), decompressed_len = sizeof(decompressed);
+    int i;
+
+    for (i = 0; i < (int)sizeof(source); i++) {
+        source[i] = "If Peter Piper picked a peck of pickled peppers"[i % 47];
+    }
+    check(BZ2_bzBuffToBuffCompress(compressed, &compressed_len, source, sizeof(source), 9, 0, 0)
+              == BZ_OK,
+          "BZ2_bzBuffToBuffCompress failed");
+    check(compressed_len < sizeof(source), "data was not compressed");
+    check(BZ2_bzBuffToBuffDecompress(decompressed, &decompressed_len, compressed, compressed_len,
+                                     0, 0)
+              == BZ_OK,
+          "BZ2_bzBuffToBuffDecompress failed");
+    check(decompressed_len == sizeof(source), "wrong decompressed length");
+    check(memcmp(source, decompressed, sizeof(source)) == 0, "wrong decompressed data");
+
+    decompressed_len = 10;
+    check(BZ2_bzBuffToBuffDecompress(decompressed, &decompressed_len, compressed, compressed_len,
+                                     0, 0)
+              == BZ_OUTBUFF_FULL,
+          "BZ_OUTBUFF_FULL expected");
+    decompressed_len = sizeof(decompressed);
+    check(BZ2_bzBuffToBuffDecompress(decompressed, &decompressed_len, source, sizeof(source), 0,
+                                     0)
+              == BZ_DATA_ERROR_MAGIC,
+          "BZ_DATA_ERROR_MAGIC expected");
+}
+
+static void test_parameters(void) {
+    bz_stream strm;
+
+    memset(&strm, 0, sizeof(strm));
+    check(BZ2_bzCompressInit(&strm, 10, 0, 0) == BZ_PARAM_ERROR, "BZ_PARAM_ERROR expected");
+    check(BZ2_bzDecompressInit(&strm, 0, 2) == BZ_PARAM_ERROR, "BZ_PARAM_ERROR expected");
+    check(BZ2_bzDecompress(&strm) == BZ_PARAM_ERROR, "BZ_PARAM_ERROR expected");
+}
+
+int main(int argc, char **argv) {
+    if (argc > 1 && strcmp(argv[1], "-z") == 0) {
+        compress_stdio();
+    } else if (argc > 1 && strcmp(argv[1], "-d") == 0) {
+        decompress_stdio();
+    } else {
+        test_buff_to_buff();
+        test_parameters();
+        printf("%s: ok\n", BZ2_bzlibVersion());
+    }
+    return 0;
+}
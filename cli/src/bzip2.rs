@@ -0,0 +1,718 @@
+//! The command line of bzip2, used when invoked as `bzip2`, `bunzip2` or `bzcat` or with bzip2
+//! style flags instead of a subcommand.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+use libribzip2::stream::{decode_stream_with_options, encode_stream, DecodeOptions};
+use libribzip2::{EncodingStrategy, Error, Level};
+
+const USAGE: &str = "
+   usage: {} [flags and input files in any order]
+
+   -h --help           print this message
+   -d --decompress     force decompression
+   -z --compress       force compression
+   -k --keep           keep (don't delete) input files
+   -f --force          overwrite existing output files
+   -t --test           test compressed file integrity
+   -c --stdout         output to standard out
+   -q --quiet          suppress noncritical error messages
+   -v --verbose        be verbose
+   -L --license        display software version & license
+   -V --version        display software version & license
+   -s --small          use less memory
+   -1 .. -9            set block size to 100k .. 900k
+   --fast              alias for -1
+   --best              alias for -9
+
+   If invoked as `bzip2', default action is to compress.
+              as `bunzip2',  default action is to decompress.
+              as `bzcat', default action is to decompress to stdout.
+
+   If no file names are given, bzip2 compresses or decompresses
+   from standard input to standard output.  You can combine
+   short flags, so `-v -4' means the same as -v4 or -4v, &c.
+";
+
+/// Exit codes of bzip2.
+const EXIT_OK: i32 = 0;
+const EXIT_ENVIRONMENT: i32 = 1;
+const EXIT_CORRUPT: i32 = 2;
+const EXIT_INTERNAL: i32 = 3;
+
+/// Names of the subcommands of ribzip2's own command line.
+const SUBCOMMANDS: [&str; 8] = [
+    "compress",
+    "decompress",
+    "recover",
+    "index",
+    "extract-range",
+    "test",
+    "info",
+    "help",
+];
+
+/// Suffixes of compressed files and the suffix of the decompressed file.
+const SUFFIXES: [(&str, &str); 4] = [
+    (".bz2", ""),
+    (".bz", ""),
+    (".tbz2", ".tar"),
+    (".tbz", ".tar"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Compress,
+    Decompress,
+    Test,
+}
+
+struct Flags {
+    mode: Mode,
+    stdout: bool,
+    keep: bool,
+    force: bool,
+    quiet: bool,
+    verbose: bool,
+    small: bool,
+    level: Level,
+    files: Vec<PathBuf>,
+}
+
+enum Action {
+    Run(Flags),
+    Help,
+    Version,
+}
+
+/// Program names which select the bzip2 command line.
+const BZIP2_NAMES: [&str; 3] = ["bzip2", "bunzip2", "bzcat"];
+
+/// Returns `true` if the arguments are meant for the bzip2 command line, either because of the
+/// program name or because the first argument is not a subcommand.
+pub(crate) fn is_bzip2_invocation(args: &[OsString]) -> bool {
+    if BZIP2_NAMES.contains(&program_name(args).as_str()) {
+        return true;
+    }
+    match args.get(1).and_then(|arg| arg.to_str()) {
+        None => false,
+        Some(arg) => {
+            !SUBCOMMANDS.contains(&arg) && !["-h", "--help", "-V", "--version"].contains(&arg)
+        }
+    }
+}
+
+fn program_name(args: &[OsString]) -> String {
+    args.first()
+        .and_then(|arg| Path::new(arg).file_stem())
+        .map_or_else(|| "ribzip2".into(), |name| name.to_string_lossy().into())
+}
+
+/// Run the bzip2 command line and return the exit code.
+pub(crate) fn main(args: Vec<OsString>) -> i32 {
+    let program = program_name(&args);
+    let name = program.clone();
+    std::panic::set_hook(Box::new(move |info| {
+        eprintln!("{}: internal error: {}", name, info);
+        // the main thread reports the panic with its exit code
+        if std::thread::current().name() != Some("main") {
+            std::process::exit(EXIT_INTERNAL);
+        }
+    }));
+
+    let args = with_env_args(args, std::env::var_os);
+    match parse(&program, args.into_iter()) {
+        Ok(Action::Run(flags)) => catch_internal_error(|| run(&program, &flags)),
+        Ok(Action::Help) => {
+            eprintln!("{}", version());
+            eprint!("{}", USAGE.replace("{}", &program));
+            EXIT_OK
+        }
+        Ok(Action::Version) => {
+            eprintln!("{}", version());
+            eprintln!("   Licensed under the MIT license.");
+            EXIT_OK
+        }
+        Err(message) => {
+            eprintln!("{}: {}", program, message);
+            eprint!("{}", USAGE.replace("{}", &program));
+            EXIT_ENVIRONMENT
+        }
+    }
+}
+
+/// The arguments after the program name, preceded by the options from the environment variables
+/// `BZIP2` and `BZIP`, so that the command line overrides them.
+fn with_env_args(
+    args: Vec<OsString>,
+    env: impl Fn(&'static str) -> Option<OsString>,
+) -> Vec<OsString> {
+    ["BZIP2", "BZIP"]
+        .into_iter()
+        .filter_map(env)
+        .flat_map(|value| {
+            value
+                .to_string_lossy()
+                .split_whitespace()
+                .map(OsString::from)
+                .collect::<Vec<_>>()
+        })
+        .chain(args.into_iter().skip(1))
+        .collect()
+}
+
+/// Returns the exit code of `run`, or [EXIT_INTERNAL] if it panics.
+fn catch_internal_error(run: impl FnOnce() -> i32 + std::panic::UnwindSafe) -> i32 {
+    std::panic::catch_unwind(run).unwrap_or(EXIT_INTERNAL)
+}
+
+fn version() -> String {
+    format!(
+        "ribzip2 {}, a bzip2 compatible block-sorting file compressor.",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn parse(program: &str, args: impl Iterator<Item = OsString>) -> Result<Action, String> {
+    let mut flags = Flags {
+        mode: Mode::Compress,
+        stdout: false,
+        keep: false,
+        force: false,
+        quiet: false,
+        verbose: false,
+        small: false,
+        level: Level::best(),
+        files: vec![],
+    };
+    match program {
+        "bunzip2" => flags.mode = Mode::Decompress,
+        "bzcat" => {
+            flags.mode = Mode::Decompress;
+            flags.stdout = true;
+        }
+        _ => {}
+    }
+
+    let mut only_files = false;
+    for arg in args {
+        let flag = match arg.to_str() {
+            Some(flag) if !only_files && flag.starts_with('-') && flag != "-" => flag,
+            _ => {
+                flags.files.push(PathBuf::from(arg));
+                continue;
+            }
+        };
+        match flag {
+            "--" => only_files = true,
+            "--compress" => flags.mode = Mode::Compress,
+            "--decompress" => flags.mode = Mode::Decompress,
+            "--test" => flags.mode = Mode::Test,
+            "--stdout" => flags.stdout = true,
+            "--keep" => flags.keep = true,
+            "--force" => flags.force = true,
+            "--quiet" => flags.quiet = true,
+            "--verbose" => flags.verbose = true,
+            "--small" => flags.small = true,
+            "--fast" => flags.level = Level::fastest(),
+            "--best" => flags.level = Level::best(),
+            "--help" => return Ok(Action::Help),
+            "--version" | "--license" => return Ok(Action::Version),
+            // accepted but without effect by bzip2 as well
+            "--repetitive-fast" | "--repetitive-best" | "--exponential" => {}
+            _ if flag.starts_with("--") => return Err(format!("Bad flag `{}'", flag)),
+            _ => {
+                for short in flag.chars().skip(1) {
+                    match short {
+                        'z' => flags.mode = Mode::Compress,
+                        'd' => flags.mode = Mode::Decompress,
+                        't' => flags.mode = Mode::Test,
+                        'c' => flags.stdout = true,
+                        'k' => flags.keep = true,
+                        'f' => flags.force = true,
+                        'q' => flags.quiet = true,
+                        'v' => flags.verbose = true,
+                        's' => flags.small = true,
+                        '1'..='9' => {
+                            flags.level = Level::new(short as u8 - b'0').unwrap();
+                        }
+                        'h' => return Ok(Action::Help),
+                        'L' | 'V' => return Ok(Action::Version),
+                        _ => return Err(format!("Bad flag `{}'", flag)),
+                    }
+                }
+            }
+        }
+    }
+    Ok(Action::Run(flags))
+}
+
+fn run(program: &str, flags: &Flags) -> i32 {
+    let to_stdout = flags.stdout || flags.files.is_empty();
+    if flags.mode == Mode::Compress && to_stdout && io::stdout().is_terminal() && !flags.force {
+        eprintln!("{}: I won't write compressed data to a terminal.", program);
+        eprintln!("{}: For help, type: `{} --help'.", program, program);
+        return EXIT_ENVIRONMENT;
+    }
+    if flags.files.is_empty() {
+        return process(program, flags, None);
+    }
+    flags
+        .files
+        .iter()
+        .map(|file_name| {
+            let input = (file_name != Path::new("-")).then_some(file_name.as_path());
+            process(program, flags, input)
+        })
+        .max()
+        .unwrap_or(EXIT_OK)
+}
+
+/// Compress, decompress or test a file, or stdin if there is no file, and return the exit code.
+fn process(program: &str, flags: &Flags, input: Option<&Path>) -> i32 {
+    let name = input.map_or_else(|| "(stdin)".into(), |input| input.display().to_string());
+    let reader: Box<dyn Read> = match input {
+        Some(input) => {
+            if input.is_dir() {
+                eprintln!("{}: Input file {} is a directory.", program, name);
+                return EXIT_ENVIRONMENT;
+            }
+            match File::open(input) {
+                Ok(in_file) => Box::new(BufReader::new(in_file)),
+                Err(error) => {
+                    eprintln!("{}: Can't open input file {}: {}.", program, name, error);
+                    return EXIT_ENVIRONMENT;
+                }
+            }
+        }
+        None if flags.mode != Mode::Compress && io::stdin().is_terminal() && !flags.force => {
+            eprintln!("{}: I won't read compressed data from a terminal.", program);
+            return EXIT_ENVIRONMENT;
+        }
+        None => Box::new(io::stdin().lock()),
+    };
+
+    let output = match input {
+        Some(input) if !flags.stdout && flags.mode != Mode::Test => {
+            match output_name(program, flags, input) {
+                Ok(output) => Some(output),
+                Err(exit_code) => return exit_code,
+            }
+        }
+        _ => None,
+    };
+    let writer: Box<dyn Write> = match (&output, flags.mode) {
+        (_, Mode::Test) => Box::new(io::sink()),
+        (Some(output), _) => match File::create(output) {
+            Ok(out_file) => Box::new(out_file),
+            Err(error) => {
+                eprintln!(
+                    "{}: Can't create output file {}: {}.",
+                    program,
+                    output.display(),
+                    error
+                );
+                return EXIT_ENVIRONMENT;
+            }
+        },
+        (None, _) => Box::new(io::stdout().lock()),
+    };
+
+    let mut reader = Counter::new(reader);
+    let mut writer = Counter::new(BufWriter::new(writer));
+    let result = match flags.mode {
+        Mode::Compress => encode_stream(
+            &mut reader,
+            &mut writer,
+            1,
+            EncodingStrategy::Single,
+            flags.level,
+        )
+        .map(|_| ()),
+        Mode::Decompress | Mode::Test => {
+            let mut options = DecodeOptions::default();
+            options.num_threads = 1;
+            options.small_memory = flags.small;
+            decode_stream_with_options(&mut reader, &mut writer, options).map(|_| ())
+        }
+    }
+    .and_then(|_| Ok(writer.flush()?));
+
+    if let Err(error) = result {
+        drop(writer);
+        if let Some(output) = &output {
+            let _ = fs::remove_file(output);
+        }
+        let error = match error {
+            // too short to hold the magic
+            Error::Io(error)
+                if error.kind() == io::ErrorKind::UnexpectedEof
+                    && flags.mode != Mode::Compress
+                    && reader.count < 4 =>
+            {
+                Error::BadMagic
+            }
+            error => error,
+        };
+        return report_error(program, flags, &name, error);
+    }
+    if let (Some(input), Some(output)) = (input, &output) {
+        copy_metadata(input, output);
+        if !flags.keep {
+            let _ = fs::remove_file(input);
+        }
+    }
+    if flags.verbose {
+        match flags.mode {
+            Mode::Compress => print_ratio(&name, reader.count, writer.count),
+            Mode::Decompress => eprintln!("  {}: done", name),
+            Mode::Test => eprintln!("  {}: ok", name),
+        }
+    }
+    EXIT_OK
+}
+
+/// The name of the output file, or the exit code if the file should be skipped.
+fn output_name(program: &str, flags: &Flags, input: &Path) -> Result<PathBuf, i32> {
+    let input_name = input.as_os_str().to_string_lossy();
+    let suffix = SUFFIXES
+        .iter()
+        .find(|(compressed, _)| input_name.ends_with(compressed));
+    let mut output = input.as_os_str().to_owned();
+    match (flags.mode, suffix) {
+        (Mode::Compress, Some((compressed, _))) => {
+            if !flags.quiet {
+                eprintln!(
+                    "{}: Input file {} already has {} suffix.",
+                    program, input_name, compressed
+                );
+            }
+            return Err(EXIT_ENVIRONMENT);
+        }
+        (Mode::Compress, None) => output.push(".bz2"),
+        (_, Some((compressed, decompressed))) => {
+            output = OsString::from(&input_name[..input_name.len() - compressed.len()]);
+            output.push(decompressed);
+        }
+        (_, None) => {
+            output.push(".out");
+            if !flags.quiet {
+                eprintln!(
+                    "{}: Can't guess original name for {} -- using {}",
+                    program,
+                    input_name,
+                    output.to_string_lossy()
+                );
+            }
+        }
+    }
+    let output = PathBuf::from(output);
+    if output.exists() {
+        if !flags.force {
+            eprintln!(
+                "{}: Output file {} already exists.",
+                program,
+                output.display()
+            );
+            return Err(EXIT_ENVIRONMENT);
+        }
+        let _ = fs::remove_file(&output);
+    }
+    Ok(output)
+}
+
+/// Print the error and return the exit code, [EXIT_CORRUPT] for invalid input.
+fn report_error(program: &str, flags: &Flags, name: &str, error: Error) -> i32 {
+    match error {
+        Error::Io(error) if error.kind() != io::ErrorKind::UnexpectedEof => {
+            eprintln!("{}: {}: {}", program, name, error);
+            EXIT_ENVIRONMENT
+        }
+        Error::BadMagic if flags.mode == Mode::Decompress => {
+            eprintln!("{}: {} is not a bzip2 file.", program, name);
+            EXIT_CORRUPT
+        }
+        error => {
+            match &error {
+                Error::Io(_) => eprintln!(
+                    "{}: {}: Compressed file ends unexpectedly;\n\tperhaps it is corrupted?",
+                    program, name
+                ),
+                error => eprintln!("{}: {}: {}", program, name, error),
+            }
+            if !matches!(error, Error::BadMagic) {
+                eprintln!(
+                    "\nYou can use `ribzip2 recover' to attempt to recover\n\
+                     data from undamaged sections of corrupted files.\n"
+                );
+            }
+            EXIT_CORRUPT
+        }
+    }
+}
+
+fn print_ratio(name: &str, bytes_in: u64, bytes_out: u64) {
+    if bytes_in == 0 {
+        eprintln!("  {}: no data compressed.", name);
+        return;
+    }
+    let (bytes_in_f, bytes_out_f) = (bytes_in as f64, bytes_out as f64);
+    eprintln!(
+        "  {}: {:6.3}:1, {:6.3} bits/byte, {:5.2}% saved, {} in, {} out.",
+        name,
+        bytes_in_f / bytes_out_f,
+        8.0 * bytes_out_f / bytes_in_f,
+        100.0 * (1.0 - bytes_out_f / bytes_in_f),
+        bytes_in,
+        bytes_out
+    );
+}
+
+/// Give the output the permissions and modification time of the input, like bzip2.
+fn copy_metadata(input: &Path, output: &Path) {
+    if let Ok(metadata) = fs::metadata(input) {
+        let _ = fs::set_permissions(output, metadata.permissions());
+        if let (Ok(modified), Ok(out_file)) = (
+            metadata.modified(),
+            File::options().write(true).open(output),
+        ) {
+            let _ = out_file.set_modified(modified);
+        }
+    }
+}
+
+/// Counts the bytes passing through a reader or writer.
+struct Counter<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counter<T> {
+    fn new(inner: T) -> Self {
+        Counter { inner, count: 0 }
+    }
+}
+
+impl<T: Read> Read for Counter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+        self.count += amount as u64;
+        Ok(amount)
+    }
+}
+
+impl<T: Write> Write for Counter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amount = self.inner.write(buf)?;
+        self.count += amount as u64;
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn flags(program: &str, arguments: &[&str]) -> Flags {
+        match parse(program, args(arguments).into_iter()) {
+            Ok(Action::Run(flags)) => flags,
+            _ => panic!("no action to run for {:?}", arguments),
+        }
+    }
+
+    /// An empty directory for the files of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ribzip2-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    pub fn dispatches_on_program_name_or_first_argument() {
+        assert!(is_bzip2_invocation(&args(&["/usr/bin/bzip2", "compress"])));
+        assert!(is_bzip2_invocation(&args(&["bunzip2"])));
+        assert!(is_bzip2_invocation(&args(&["bzcat.exe", "file.bz2"])));
+        assert!(is_bzip2_invocation(&args(&["ribzip2", "-d", "file.bz2"])));
+        assert!(is_bzip2_invocation(&args(&["ribzip2", "file"])));
+        assert!(is_bzip2_invocation(&args(&["ribzip2-x86_64", "-dc"])));
+        assert!(!is_bzip2_invocation(&args(&["ribzip2"])));
+        assert!(!is_bzip2_invocation(&args(&["ribzip2", "--help"])));
+        assert!(!is_bzip2_invocation(&args(&[
+            "ribzip2", "compress", "file"
+        ])));
+        assert!(!is_bzip2_invocation(&args(&[
+            "ribzip2-x86_64",
+            "compress",
+            "f"
+        ])));
+        assert!(!is_bzip2_invocation(&args(&[
+            "./target/ribzip2",
+            "info",
+            "f"
+        ])));
+    }
+
+    #[test]
+    pub fn selects_mode_by_program_name() {
+        assert_eq!(flags("bzip2", &[]).mode, Mode::Compress);
+        assert_eq!(flags("ribzip2", &[]).mode, Mode::Compress);
+        assert_eq!(flags("bunzip2", &[]).mode, Mode::Decompress);
+        let bzcat = flags("bzcat", &[]);
+        assert_eq!(bzcat.mode, Mode::Decompress);
+        assert!(bzcat.stdout);
+        assert_eq!(flags("bunzip2", &["-z"]).mode, Mode::Compress);
+    }
+
+    #[test]
+    pub fn combines_short_flags() {
+        let parsed = flags("bzip2", &["-v4", "file"]);
+        assert!(parsed.verbose);
+        assert_eq!(parsed.level, Level::new(4).unwrap());
+        assert_eq!(parsed.files, vec![PathBuf::from("file")]);
+
+        let parsed = flags("bzip2", &["-dc"]);
+        assert_eq!(parsed.mode, Mode::Decompress);
+        assert!(parsed.stdout);
+
+        let parsed = flags("bzip2", &["-4v", "-kfqs", "--fast"]);
+        assert!(parsed.verbose && parsed.keep && parsed.force && parsed.quiet && parsed.small);
+        assert_eq!(parsed.level, Level::fastest());
+
+        assert!(parse("bzip2", args(&["-dx"]).into_iter()).is_err());
+        assert!(parse("bzip2", args(&["--bad"]).into_iter()).is_err());
+        assert!(matches!(
+            parse("bzip2", args(&["-vh"]).into_iter()),
+            Ok(Action::Help)
+        ));
+    }
+
+    #[test]
+    pub fn takes_arguments_after_double_dash_as_files() {
+        let parsed = flags("bzip2", &["-", "--", "-v", "--best"]);
+        assert!(!parsed.verbose);
+        assert_eq!(
+            parsed.files,
+            vec![PathBuf::from("-"), "-v".into(), "--best".into()]
+        );
+    }
+
+    #[test]
+    pub fn command_line_overrides_environment() {
+        let env = |name| match name {
+            "BZIP2" => Some(OsString::from("-3 -q")),
+            "BZIP" => Some(OsString::from(" -5  -v ")),
+            _ => None,
+        };
+        let combined = with_env_args(args(&["bzip2", "-7", "file"]), env);
+        assert_eq!(combined, args(&["-3", "-q", "-5", "-v", "-7", "file"]));
+        let parsed = flags("bzip2", &["-3", "-q", "-5", "-v", "-7", "file"]);
+        assert!(parsed.quiet && parsed.verbose);
+        assert_eq!(parsed.level, Level::new(7).unwrap());
+
+        // BZIP is read after BZIP2
+        let combined = with_env_args(args(&["bzip2"]), env);
+        let parsed = parse("bzip2", combined.into_iter());
+        assert!(matches!(parsed, Ok(Action::Run(flags)) if flags.level == Level::new(5).unwrap()));
+        assert_eq!(
+            with_env_args(args(&["bzip2", "-d"]), |_| None),
+            args(&["-d"])
+        );
+    }
+
+    #[test]
+    pub fn maps_suffixes() {
+        let dir = test_dir("suffixes");
+        let decompress = flags("bunzip2", &["-q"]);
+        for (input, output) in [
+            ("a.bz2", "a"),
+            ("a.bz", "a"),
+            ("a.tbz2", "a.tar"),
+            ("a.tbz", "a.tar"),
+            ("a.txt", "a.txt.out"),
+        ] {
+            assert_eq!(
+                output_name("bunzip2", &decompress, &dir.join(input)),
+                Ok(dir.join(output))
+            );
+        }
+
+        let compress = flags("bzip2", &["-q"]);
+        assert_eq!(
+            output_name("bzip2", &compress, &dir.join("a.tar")),
+            Ok(dir.join("a.tar.bz2"))
+        );
+        assert_eq!(
+            output_name("bzip2", &compress, &dir.join("a.tbz")),
+            Err(EXIT_ENVIRONMENT)
+        );
+
+        fs::write(dir.join("a.tar"), b"exists").unwrap();
+        assert_eq!(
+            output_name("bunzip2", &decompress, &dir.join("a.tbz")),
+            Err(EXIT_ENVIRONMENT)
+        );
+        let force = flags("bunzip2", &["-qf"]);
+        assert_eq!(
+            output_name("bunzip2", &force, &dir.join("a.tbz")),
+            Ok(dir.join("a.tar"))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn returns_exit_codes_of_bzip2() {
+        let dir = test_dir("exit-codes");
+        let input = dir.join("data.txt");
+        fs::write(&input, b"If Peter Piper picked a peck of pickled peppers").unwrap();
+
+        let compress = flags("bzip2", &["-q"]);
+        assert_eq!(process("bzip2", &compress, Some(&input)), EXIT_OK);
+        assert!(!input.exists());
+        let compressed = dir.join("data.txt.bz2");
+        let test = flags("bzip2", &["-tq"]);
+        assert_eq!(process("bzip2", &test, Some(&compressed)), EXIT_OK);
+        let decompress = flags("bunzip2", &["-kq"]);
+        assert_eq!(process("bunzip2", &decompress, Some(&compressed)), EXIT_OK);
+        assert_eq!(
+            fs::read(&input).unwrap(),
+            b"If Peter Piper picked a peck of pickled peppers"
+        );
+
+        // missing input and existing output
+        let missing = dir.join("missing.bz2");
+        assert_eq!(
+            process("bunzip2", &decompress, Some(&missing)),
+            EXIT_ENVIRONMENT
+        );
+        assert_eq!(
+            process("bunzip2", &decompress, Some(&compressed)),
+            EXIT_ENVIRONMENT
+        );
+
+        // no bzip2 file, and damaged data after the header
+        let not_bzip2 = dir.join("plain.bz2");
+        fs::write(&not_bzip2, b"plain text").unwrap();
+        assert_eq!(process("bunzip2", &test, Some(&not_bzip2)), EXIT_CORRUPT);
+        let mut damaged = fs::read(&compressed).unwrap();
+        let middle = damaged.len() / 2;
+        damaged[middle] ^= 0xff;
+        fs::write(&compressed, damaged).unwrap();
+        assert_eq!(process("bunzip2", &test, Some(&compressed)), EXIT_CORRUPT);
+
+        assert_eq!(catch_internal_error(|| EXIT_OK), EXIT_OK);
+        assert_eq!(catch_internal_error(|| panic!("bug")), EXIT_INTERNAL);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use libribzip2::inspect::{inspect, BlockInfo, InspectMode, StreamInfo};
+
+use crate::fail;
+
+/// Statistics of a compressed file, gathered by decoding all of its blocks.
+struct FileInfo {
+    name: String,
+    compressed_size: u64,
+    blocks: Vec<BlockInfo>,
+    streams: Vec<StreamInfo>,
+}
+
+impl FileInfo {
+    fn read(file_name: &Path) -> Self {
+        let in_file = File::open(file_name).unwrap_or_else(|error| fail(file_name, error.into()));
+        let compressed_size = in_file
+            .metadata()
+            .unwrap_or_else(|error| fail(file_name, error.into()))
+            .len();
+        let mut inspected = inspect(BufReader::new(in_file), InspectMode::FullDecode);
+        let blocks = inspected
+            .by_ref()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|error| fail(file_name, error));
+        FileInfo {
+            name: file_name.display().to_string(),
+            compressed_size,
+            blocks,
+            streams: inspected.streams().to_vec(),
+        }
+    }
+
+    fn uncompressed_size(&self) -> u64 {
+        self.blocks
+            .iter()
+            .filter_map(|block| block.uncompressed_size)
+            .sum()
+    }
+
+    /// Uncompressed size relative to the compressed size, like the N:1 printed by bzip2.
+    fn ratio(&self) -> f64 {
+        self.uncompressed_size() as f64 / self.compressed_size.max(1) as f64
+    }
+
+    fn bits_per_byte(&self) -> f64 {
+        8.0 * self.compressed_size as f64 / self.uncompressed_size().max(1) as f64
+    }
+
+    /// Level of the first stream, which is the only one in files written by bzip2.
+    fn level(&self) -> Option<u8> {
+        self.streams.first().map(|stream| stream.level.value())
+    }
+
+    fn print(&self) {
+        let crcs = self
+            .streams
+            .iter()
+            .map(|stream| format!("{:#010x}", stream.crc))
+            .collect::<Vec<_>>();
+        println!("{}:", self.name);
+        println!("  blocks:            {}", self.blocks.len());
+        if let Some(level) = self.level() {
+            println!("  level:             {}", level);
+        }
+        println!("  compressed size:   {}", self.compressed_size);
+        println!("  uncompressed size: {}", self.uncompressed_size());
+        println!("  ratio:             {:.3}:1", self.ratio());
+        println!("  bits per byte:     {:.3}", self.bits_per_byte());
+        println!("  stream CRC:        {}", crcs.join(", "));
+        println!(
+            "  {:>6} {:>6} {:>12} {:>10} {:>10} {:>10} {:>6} {:>9} {:>4}",
+            "block", "stream", "bit offset", "bits", "size", "CRC", "tables", "selectors", "ok"
+        );
+        for block in &self.blocks {
+            println!(
+                "  {:>6} {:>6} {:>12} {:>10} {:>10} {:#010x} {:>6} {:>9} {:>4}",
+                block.index,
+                block.stream,
+                block.bit_offset,
+                block.bit_length,
+                block.uncompressed_size.unwrap_or_default(),
+                block.crc,
+                block.num_tables,
+                block.num_selectors,
+                if block.crc_valid == Some(true) {
+                    "yes"
+                } else {
+                    "no"
+                }
+            );
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| {
+                format!(
+                    "{{\"index\":{},\"stream\":{},\"bit_offset\":{},\"bit_length\":{},\
+                     \"uncompressed_size\":{},\"crc\":{},\"crc_valid\":{},\"randomized\":{},\
+                     \"tables\":{},\"selectors\":{}}}",
+                    block.index,
+                    block.stream,
+                    block.bit_offset,
+                    block.bit_length,
+                    block.uncompressed_size.unwrap_or_default(),
+                    block.crc,
+                    block.crc_valid == Some(true),
+                    block.randomized,
+                    block.num_tables,
+                    block.num_selectors
+                )
+            })
+            .collect::<Vec<_>>();
+        let crcs = self
+            .streams
+            .iter()
+            .map(|stream| stream.crc.to_string())
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"file\":{},\"blocks\":{},\"level\":{},\"compressed_size\":{},\
+             \"uncompressed_size\":{},\"ratio\":{:.6},\"bits_per_byte\":{:.6},\
+             \"stream_crcs\":[{}],\"block_list\":[{}]}}",
+            json_string(&self.name),
+            self.blocks.len(),
+            self.level()
+                .map_or_else(|| "null".to_string(), |level| level.to_string()),
+            self.compressed_size,
+            self.uncompressed_size(),
+            self.ratio(),
+            self.bits_per_byte(),
+            crcs.join(","),
+            blocks.join(",")
+        )
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Print statistics of the files, as a JSON array with one object per file if requested.
+pub(crate) fn info(files: &[impl AsRef<Path>], json: bool) {
+    let infos = files
+        .iter()
+        .map(|file_name| FileInfo::read(file_name.as_ref()));
+    if json {
+        let objects = infos.map(|info| info.to_json()).collect::<Vec<_>>();
+        println!("[{}]", objects.join(","));
+    } else {
+        infos.for_each(|info| info.print());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libribzip2::Compression;
+    use std::io::Write;
+
+    #[test]
+    pub fn escapes_json_strings() {
+        assert_eq!(json_string("file.bz2"), "\"file.bz2\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("a\nb\u{1f}"), "\"a\\u000ab\\u001f\"");
+        assert_eq!(json_string("ä"), "\"ä\"");
+    }
+
+    #[test]
+    pub fn writes_json() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(100);
+        let mut encoder = libribzip2::write::BzEncoder::new(vec![], Compression::new(7));
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let file_name = std::env::temp_dir().join(format!("ribzip2-info-{}", std::process::id()));
+        std::fs::write(&file_name, &compressed).unwrap();
+        let info = FileInfo::read(&file_name);
+        std::fs::remove_file(&file_name).unwrap();
+
+        let json = info.to_json();
+        assert!(json.starts_with(&format!("{{\"file\":{},", json_string(&info.name))));
+        for field in [
+            "\"blocks\":1,".to_string(),
+            "\"level\":7,".to_string(),
+            format!("\"compressed_size\":{},", compressed.len()),
+            format!("\"uncompressed_size\":{},", data.len()),
+            format!(
+                "\"ratio\":{:.6},",
+                data.len() as f64 / compressed.len() as f64
+            ),
+            format!("\"stream_crcs\":[{}],", info.streams[0].crc),
+            "\"crc_valid\":true,".to_string(),
+        ] {
+            assert!(json.contains(&field), "{} not in {}", field, json);
+        }
+        assert!(json.ends_with("}]}"));
+    }
+}
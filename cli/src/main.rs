@@ -1,24 +1,98 @@
-use libribzip2::stream::{decode_stream, encode_stream};
-use libribzip2::EncodingStrategy;
+mod bzip2;
+mod info;
+
+use libribzip2::index::BlockIndex;
+use libribzip2::recover::recover_blocks;
+use libribzip2::seekable::extract_range;
+use libribzip2::stream::{decode_stream_with_options, encode_stream, DecodeOptions};
+use libribzip2::{EncodingStrategy, Level};
 use std::fs::File;
-use std::path::PathBuf;
-use std::{ffi::OsString, io::BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::{
+    ffi::OsString,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 enum Opt {
+    /// Decompress files to <file>.out, or stdin to stdout if no file or - is given
     Decompress {
-        #[structopt(parse(from_os_str), required = true)]
+        #[structopt(parse(from_os_str))]
         input: Vec<PathBuf>,
+        #[structopt(default_value = "1", long)]
+        threads: usize,
+        /// Skip damaged blocks and continue with the next intact one
+        #[structopt(long)]
+        skip_bad_blocks: bool,
+        /// Write to stdout instead of files
+        #[structopt(short = "c", long)]
+        stdout: bool,
+        /// Use less memory for decoding blocks, which is slightly slower
+        #[structopt(short = "s", long)]
+        small: bool,
     },
+    /// Compress files to <file>.bz2, or stdin to stdout if no file or - is given
     Compress {
-        #[structopt(parse(from_os_str), required = true)]
+        #[structopt(parse(from_os_str))]
         input: Vec<PathBuf>,
         #[structopt(default_value = "1", long)]
         threads: usize,
+        /// Write to stdout instead of files
+        #[structopt(short = "c", long)]
+        stdout: bool,
+        /// Compression level from 1 (100k blocks) to 9 (900k blocks), -1 .. -9 are shorthands
+        #[structopt(default_value = "9", long, parse(try_from_str = parse_level))]
+        level: Level,
+        /// Also write a block index to <file>.bz2.idx
+        #[structopt(long)]
+        index: bool,
         #[structopt(subcommand)]
         encoding_options: Option<EncodingOptions>,
     },
+    /// Salvage intact blocks of a damaged file into files rec00001<file>, rec00002<file>, ...
+    Recover {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Write a block index for random access to <file>.idx
+    Index {
+        #[structopt(parse(from_os_str), required = true)]
+        input: Vec<PathBuf>,
+        #[structopt(default_value = "1", long)]
+        threads: usize,
+    },
+    /// Check the integrity of compressed files by decoding them, like bzip2 -t
+    Test {
+        #[structopt(parse(from_os_str), required = true)]
+        input: Vec<PathBuf>,
+        #[structopt(default_value = "1", long)]
+        threads: usize,
+    },
+    /// Print the sizes, CRCs and blocks of compressed files
+    Info {
+        #[structopt(parse(from_os_str), required = true)]
+        input: Vec<PathBuf>,
+        /// Print a JSON array with an object per file
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Write a range of the uncompressed data to stdout or a file, decoding only the blocks
+    /// overlapping it. Uses the block index in <file>.idx if present, otherwise the blocks up to
+    /// the range are indexed first
+    ExtractRange {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Offset of the first byte in the uncompressed data
+        #[structopt(long)]
+        offset: u64,
+        #[structopt(long)]
+        length: u64,
+        /// Output file instead of stdout
+        #[structopt(parse(from_os_str), long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(StructOpt, Clone, Copy)]
@@ -32,43 +106,111 @@ pub(crate) enum EncodingOptions {
     },
 }
 
+fn parse_level(level: &str) -> Result<Level, String> {
+    level
+        .parse()
+        .ok()
+        .and_then(Level::new)
+        .ok_or_else(|| format!("invalid level {}, expected 1 to 9", level))
+}
+
+/// Rewrites bzip2 style level flags `-1` .. `-9` of the `compress` subcommand to `--level`.
+/// Arguments after `--` are file names and kept as they are.
+fn expand_level_flags(args: Vec<OsString>) -> Vec<OsString> {
+    let mut options = args.get(1).is_some_and(|arg| arg == "compress");
+    args.into_iter()
+        .map(|arg| {
+            if arg == "--" {
+                options = false;
+            }
+            match arg.to_str() {
+                Some(flag) if options && matches!(flag.as_bytes(), [b'-', b'1'..=b'9']) => {
+                    OsString::from(format!("--level={}", &flag[1..]))
+                }
+                _ => arg,
+            }
+        })
+        .collect()
+}
+
 fn main() {
-    let opt = Opt::from_args();
+    let args = std::env::args_os().collect::<Vec<_>>();
+    if bzip2::is_bzip2_invocation(&args) {
+        exit(bzip2::main(args));
+    }
+    let opt = Opt::from_iter(expand_level_flags(args));
     match opt {
-        Opt::Decompress { input } => {
-            for file_name in input {
-                let mut out_file_name = file_name.clone();
-                out_file_name.set_extension(OsString::from("out"));
-                let out_file = File::create(out_file_name).expect("Could not create file.");
-                let mut in_file = File::open(file_name).unwrap();
-                decode_stream(&mut in_file, out_file).unwrap();
+        Opt::Decompress {
+            input,
+            threads,
+            skip_bad_blocks,
+            stdout,
+            small,
+        } => {
+            let mut options = DecodeOptions::default();
+            options.num_threads = threads;
+            options.skip_bad_blocks = skip_bad_blocks;
+            options.small_memory = small;
+            let mut damaged = false;
+            for input in input_files(input) {
+                let out_file_name = input.as_ref().filter(|_| !stdout).map(|file_name| {
+                    let mut out_file_name = file_name.clone();
+                    out_file_name.set_extension(OsString::from("out"));
+                    out_file_name
+                });
+                let file_name = display_name(&input);
+                let mut writer = create_output(out_file_name.as_deref());
+                let report = decode_stream_with_options(open_input(&input), &mut writer, options)
+                    .and_then(|report| writer.flush().map(|_| report).map_err(Into::into))
+                    .unwrap_or_else(|error| fail(file_name, error));
+                for block in &report.damaged_blocks {
+                    let lost = block
+                        .lost_bytes
+                        .map_or_else(|| "unknown".to_string(), |bytes| bytes.to_string());
+                    eprintln!(
+                        "ribzip2: {}: skipped block {} at bit {}, {} bytes lost: {}",
+                        file_name.display(),
+                        block.index,
+                        block.bit_offset,
+                        lost,
+                        block.error
+                    );
+                }
+                damaged |= !report.is_clean();
+            }
+            if damaged {
+                exit(1);
             }
         }
         Opt::Compress {
             input,
             threads,
+            level,
+            index,
             encoding_options,
+            stdout,
         } => {
-            for file_name in input {
-                let mut out_file_name = file_name.clone();
-                let extension = out_file_name.extension().map(|x| {
-                    let mut y = x.to_os_string();
-                    y.push(".bz2");
-                    y
-                });
+            for input in input_files(input) {
+                let out_file_name = input.as_ref().filter(|_| !stdout).map(|file_name| {
+                    let mut out_file_name = file_name.clone();
+                    let extension = out_file_name.extension().map(|x| {
+                        let mut y = x.to_os_string();
+                        y.push(".bz2");
+                        y
+                    });
 
-                match extension {
-                    Some(ext) => {
-                        out_file_name.set_extension(ext);
+                    match extension {
+                        Some(ext) => {
+                            out_file_name.set_extension(ext);
+                        }
+                        None => {
+                            out_file_name.set_extension(OsString::from("bz2"));
+                        }
                     }
-                    None => {
-                        out_file_name.set_extension(OsString::from("bz2"));
-                    }
-                }
-
-                let out_file = File::create(out_file_name).expect("Could not create File.");
-                let mut out_file = BufWriter::new(out_file);
-                let mut in_file = File::open(file_name).unwrap();
+                    out_file_name
+                });
+                let file_name = display_name(&input);
+                let mut writer = create_output(out_file_name.as_deref());
                 let encoding_strategy = match encoding_options {
                     Some(EncodingOptions::Single) | None => EncodingStrategy::Single,
                     Some(EncodingOptions::KMeans {
@@ -79,8 +221,195 @@ fn main() {
                         num_clusters: num_tables,
                     },
                 };
-                encode_stream(&mut in_file, &mut out_file, threads, encoding_strategy);
+                let block_index = encode_stream(
+                    open_input(&input),
+                    &mut writer,
+                    threads,
+                    encoding_strategy,
+                    level,
+                )
+                .and_then(|block_index| writer.flush().map(|_| block_index).map_err(Into::into))
+                .unwrap_or_else(|error| fail(file_name, error));
+                match &out_file_name {
+                    Some(out_file_name) if index => write_index(out_file_name, &block_index),
+                    None if index => eprintln!("ribzip2: no block index written for stdout"),
+                    _ => {}
+                }
             }
         }
+        Opt::Recover { input } => recover(&input),
+        Opt::Test { input, threads } => {
+            let mut options = DecodeOptions::default();
+            options.num_threads = threads;
+            let mut failed = false;
+            for file_name in input {
+                let result = File::open(&file_name)
+                    .map_err(libribzip2::Error::from)
+                    .and_then(|in_file| {
+                        decode_stream_with_options(BufReader::new(in_file), io::sink(), options)
+                    });
+                match result {
+                    Ok(_) => println!("{}: OK", file_name.display()),
+                    Err(error) => {
+                        eprintln!("ribzip2: {}: {}", file_name.display(), error);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                exit(1);
+            }
+        }
+        Opt::Info { input, json } => info::info(&input, json),
+        Opt::ExtractRange {
+            input,
+            offset,
+            length,
+            output,
+        } => extract(&input, offset, length, output.as_deref()),
+        Opt::Index { input, threads } => {
+            for file_name in input {
+                let in_file =
+                    File::open(&file_name).unwrap_or_else(|error| fail(&file_name, error.into()));
+                let block_index = BlockIndex::scan(BufReader::new(in_file), threads)
+                    .unwrap_or_else(|error| fail(&file_name, error));
+                write_index(&file_name, &block_index);
+                println!(
+                    "{}: {} blocks, {} bytes uncompressed",
+                    file_name.display(),
+                    block_index.len(),
+                    block_index.uncompressed_size()
+                );
+            }
+        }
+    }
+}
+
+/// The input files, where `None` stands for stdin. Without files stdin is read.
+fn input_files(input: Vec<PathBuf>) -> Vec<Option<PathBuf>> {
+    if input.is_empty() {
+        return vec![None];
+    }
+    input
+        .into_iter()
+        .map(|file_name| (file_name != Path::new("-")).then_some(file_name))
+        .collect()
+}
+
+fn display_name(input: &Option<PathBuf>) -> &Path {
+    input.as_deref().unwrap_or_else(|| Path::new("(stdin)"))
+}
+
+fn open_input(input: &Option<PathBuf>) -> Box<dyn Read> {
+    match input {
+        Some(file_name) => {
+            let in_file =
+                File::open(file_name).unwrap_or_else(|error| fail(file_name, error.into()));
+            Box::new(BufReader::new(in_file))
+        }
+        None => Box::new(io::stdin().lock()),
+    }
+}
+
+/// Creates the output file, or writes to stdout if there is none.
+fn create_output(out_file_name: Option<&Path>) -> BufWriter<Box<dyn Write>> {
+    match out_file_name {
+        Some(out_file_name) => {
+            let out_file = File::create(out_file_name).expect("Could not create file.");
+            BufWriter::new(Box::new(out_file))
+        }
+        None => BufWriter::new(Box::new(io::stdout().lock())),
+    }
+}
+
+fn extract(file_name: &Path, offset: u64, length: u64, output: Option<&Path>) {
+    let sidecar = BlockIndex::sidecar_path(file_name);
+    // without a sidecar the blocks are located by decoding
+    let index = match File::open(&sidecar) {
+        Ok(index_file) => Some(
+            BlockIndex::read_from(BufReader::new(index_file))
+                .unwrap_or_else(|error| fail(&sidecar, error.into())),
+        ),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => fail(&sidecar, error.into()),
+    };
+    let in_file = File::open(file_name).unwrap_or_else(|error| fail(file_name, error.into()));
+    let result = match output {
+        Some(output) => {
+            let out_file = File::create(output).expect("Could not create file.");
+            extract_range(in_file, index, offset, length, BufWriter::new(out_file))
+        }
+        None => extract_range(in_file, index, offset, length, io::stdout().lock()),
+    };
+    if let Err(error) = result {
+        fail(file_name, error);
+    }
+}
+
+fn write_index(file_name: &Path, block_index: &BlockIndex) {
+    let index_file =
+        File::create(BlockIndex::sidecar_path(file_name)).expect("Could not create file.");
+    block_index
+        .write_to(BufWriter::new(index_file))
+        .expect("Could not write file.");
+}
+
+fn recover(file_name: &Path) {
+    // the recovered blocks are named after the input file
+    let Some(base_name) = file_name.file_name().map(|name| name.to_string_lossy()) else {
+        eprintln!("ribzip2: {}: not a file name", file_name.display());
+        exit(1);
+    };
+    let in_file = File::open(file_name).unwrap_or_else(|error| fail(file_name, error.into()));
+    let mut intact = 0;
+    let mut found = 0;
+    for block in recover_blocks(BufReader::new(in_file)) {
+        let block = block.unwrap_or_else(|error| fail(file_name, error));
+        found += 1;
+        match &block.error {
+            None => {
+                let out_file_name =
+                    file_name.with_file_name(format!("rec{:05}{}", block.index + 1, base_name));
+                std::fs::write(&out_file_name, &block.stream).expect("Could not write file.");
+                intact += 1;
+                println!(
+                    "block {} at bit {}: intact, written to {}",
+                    block.index + 1,
+                    block.bit_offset,
+                    out_file_name.display()
+                );
+            }
+            Some(error) => println!(
+                "block {} at bit {}: damaged ({})",
+                block.index + 1,
+                block.bit_offset,
+                error
+            ),
+        }
+    }
+    println!("{} of {} blocks intact", intact, found);
+}
+
+fn fail(file_name: &Path, error: libribzip2::Error) -> ! {
+    eprintln!("ribzip2: {}: {}", file_name.display(), error);
+    exit(1);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    pub fn expands_level_flags_of_compress() {
+        assert_eq!(
+            expand_level_flags(args(&["ribzip2", "compress", "-9", "a", "--", "-1"])),
+            args(&["ribzip2", "compress", "--level=9", "a", "--", "-1"])
+        );
+        let decompress = args(&["ribzip2", "decompress", "-1"]);
+        assert_eq!(expand_level_flags(decompress.clone()), decompress);
     }
 }
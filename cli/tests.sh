@@ -20,3 +20,38 @@ cargo run -- compress samples/idiot.txt
 cargo run -- decompress samples/idiot.txt.bz2
 rm samples/idiot.txt.bz2
 rm samples/idiot.txt.out
+
+# bzip2 compatible command line when invoked as bzip2, interoperating with bzip2
+cargo build
+cp ../target/debug/ribzip2 temp/bzip2
+cp samples/idiot.txt temp/
+temp/bzip2 -9 temp/idiot.txt
+bunzip2 -c temp/idiot.txt.bz2 | cmp - samples/idiot.txt
+temp/bzip2 -d temp/idiot.txt.bz2
+cmp temp/idiot.txt samples/idiot.txt
+bzip2 -c samples/pepper.txt | temp/bzip2 -dc | cmp - samples/pepper.txt
+status=0
+temp/bzip2 -t samples/pepper.txt || status=$?
+test $status -eq 2
+status=0
+temp/bzip2 -d temp/missing.bz2 || status=$?
+test $status -eq 1
+rm temp/bzip2 temp/idiot.txt
+
+# integrity check of an intact and a corrupted file
+cargo run -- compress samples/pepper.txt
+mv samples/pepper.txt.bz2 temp/
+cargo run -- test temp/pepper.txt.bz2
+printf '\377' | dd of=temp/pepper.txt.bz2 bs=1 seek=40 conv=notrunc
+status=0
+cargo run -- test temp/pepper.txt.bz2 || status=$?
+test $status -eq 1
+rm temp/pepper.txt.bz2
+
+# streaming from stdin to stdout, without files or with -
+cat samples/pepper.txt | cargo run -- compress > temp/pepper.txt.bz2
+bunzip2 -c temp/pepper.txt.bz2 | cmp - samples/pepper.txt
+cargo run -- decompress -c temp/pepper.txt.bz2 | cmp - samples/pepper.txt
+cargo run -- decompress - < temp/pepper.txt.bz2 | cmp - samples/pepper.txt
+cargo run -- compress - < samples/idiot.txt | bunzip2 | cmp - samples/idiot.txt
+rm temp/pepper.txt.bz2
@@ -1,21 +1,42 @@
-use std::io::Read;
+use std::io::{BufRead, ErrorKind};
 
-use crate::bitwise::bitwriter::convert_to_number;
+use crate::Error;
 
-use super::Bit;
-
-pub struct BitReaderImpl<'a, T: Read> {
-    byte_reader: &'a mut T,
-    current_byte_cursor: u8,
-    current_byte: Option<u8>,
+/// Reads bits most significant bit first. The accumulator is refilled with up to 8 bytes at a
+/// time from the buffer of the underlying reader. Bytes are only consumed from it once all of
+/// their bits have been read, so that [BitReaderImpl::into_inner] hands back the bytes which
+/// have not been read completely. Only when the accumulator needs more bits than the buffer of
+/// the reader holds, the bytes already in the accumulator are consumed early to get the next
+/// buffer.
+pub struct BitReaderImpl<T: BufRead> {
+    byte_reader: T,
+    /// The buffered bits, aligned to the most significant bit. Unused bits are zero.
+    accumulator: u64,
+    /// Number of bits in the accumulator.
+    buffered: u32,
+    /// Number of bytes at the start of the buffer of the reader which are already in the
+    /// accumulator.
+    unconsumed: usize,
+    position: u64,
 }
 
 pub trait BitReader {
-    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, ()>;
-    fn read_bytes(&mut self, number: usize) -> Result<Vec<u8>, ()> {
-        let mut out = vec![];
+    /// Read `num` bits (at most 32) and return them as the lowest bits of a number.
+    fn read_u32(&mut self, num: u32) -> Result<u32, Error>;
+    /// Returns the next `num` bits (at most 32) without consuming them. Missing bits at the
+    /// end of the input are returned as zeros.
+    fn peek_u32(&mut self, num: u32) -> Result<u32, Error>;
+    /// Skip `num` bits, which fails if fewer bits are left.
+    fn consume(&mut self, num: u32) -> Result<(), Error>;
+    /// Number of bits read so far.
+    fn position(&self) -> u64;
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        Ok(self.read_u32(1)? == 1)
+    }
+    fn read_bytes(&mut self, number: usize) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(number);
         for _ in 0..number {
-            out.push(convert_to_number(&self.read_bits(8)?).try_into().unwrap());
+            out.push(self.read_u32(8)? as u8);
         }
         Ok(out)
     }
@@ -25,111 +46,180 @@ impl<T> BitReader for &mut T
 where
     T: BitReader,
 {
-    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, ()> {
-        (**self).read_bits(num)
+    fn read_u32(&mut self, num: u32) -> Result<u32, Error> {
+        (**self).read_u32(num)
+    }
+
+    fn peek_u32(&mut self, num: u32) -> Result<u32, Error> {
+        (**self).peek_u32(num)
+    }
+
+    fn consume(&mut self, num: u32) -> Result<(), Error> {
+        (**self).consume(num)
+    }
+
+    fn position(&self) -> u64 {
+        (**self).position()
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        (**self).read_bit()
     }
 }
 
-impl<'a, T: Read> BitReaderImpl<'a, T> {
-    pub fn from_reader(reader: &'a mut T) -> Self {
+impl<T: BufRead> BitReaderImpl<T> {
+    pub fn from_reader(reader: T) -> Self {
         BitReaderImpl {
             byte_reader: reader,
-            current_byte_cursor: 0u8,
-            current_byte: None,
+            accumulator: 0,
+            buffered: 0,
+            unconsumed: 0,
+            position: 0,
         }
     }
-}
 
-impl<'a, T: Read> BitReader for BitReaderImpl<'a, T> {
-    fn read_bits(&mut self, mut num: usize) -> Result<Vec<Bit>, ()> {
-        let mut out = vec![];
-        let mut buf = [0u8; 1];
-
-        while num > 0 {
-            match self.current_byte {
-                Some(current_byte) => {
-                    if self.current_byte_cursor >= 8 {
-                        self.byte_reader.read_exact(&mut buf).map_err(|_| ())?;
-                        self.current_byte = Some(buf[0]);
-                        self.current_byte_cursor = 0;
-                    } else {
-                        let offset = 8 - self.current_byte_cursor - 1;
-                        let current_bit = (current_byte >> offset) & 1;
-                        out.push(if current_bit == 0 {
-                            Bit::Zero
-                        } else {
-                            Bit::One
-                        });
-
-                        self.current_byte_cursor += 1;
-                        num -= 1;
-                    }
-                }
-                None => {
-                    self.byte_reader.read_exact(&mut buf).map_err(|_| ())?;
-                    self.current_byte = Some(buf[0]);
-                    self.current_byte_cursor = 0;
-                }
+    pub fn get_ref(&self) -> &T {
+        &self.byte_reader
+    }
+
+    /// The underlying reader, positioned at the first byte which has not been read completely
+    /// (see [BitReaderImpl]).
+    pub fn get_mut(&mut self) -> &mut T {
+        self.consume_read_bytes();
+        &mut self.byte_reader
+    }
+
+    /// Returns the underlying reader, positioned like with [BitReaderImpl::get_mut].
+    pub fn into_inner(mut self) -> T {
+        self.consume_read_bytes();
+        self.byte_reader
+    }
+
+    /// Skip the remaining bits of a partially consumed byte.
+    pub fn align_to_byte(&mut self) {
+        let partial = self.buffered % 8;
+        self.advance(partial);
+    }
+
+    /// Continue at the given bit position, after the underlying reader has been moved to the
+    /// start of its byte.
+    pub(crate) fn restart_at(&mut self, position: u64) -> Result<(), Error> {
+        self.accumulator = 0;
+        self.buffered = 0;
+        self.unconsumed = 0;
+        self.position = position - position % 8;
+        self.read_u32((position % 8) as u32)?;
+        Ok(())
+    }
+
+    /// Move up to 8 bytes from the buffer of the reader into the accumulator, returns `false` at
+    /// the end of the input.
+    fn refill(&mut self) -> Result<bool, Error> {
+        self.consume_read_bytes();
+        loop {
+            let available = match self.byte_reader.fill_buf() {
+                Ok(available) => available,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+            let new = &available[self.unconsumed.min(available.len())..];
+            let amount = (((64 - self.buffered) / 8) as usize).min(new.len());
+            if amount > 0 {
+                let mut bytes = [0u8; 8];
+                bytes[..amount].copy_from_slice(&new[..amount]);
+                self.accumulator |= u64::from_be_bytes(bytes) >> self.buffered;
+                self.buffered += 8 * amount as u32;
+                self.unconsumed += amount;
+                return Ok(true);
             }
+            if self.unconsumed == 0 {
+                return Ok(false);
+            }
+            self.byte_reader.consume(self.unconsumed);
+            self.unconsumed = 0;
         }
-        Ok(out)
     }
-}
 
-#[cfg(test)]
-pub struct InMemoryBitReader {
-    bits: Vec<Bit>,
-    cursor: usize,
-}
+    /// Consume the bytes whose bits have all been read from the underlying reader.
+    fn consume_read_bytes(&mut self) {
+        // bytes touched by the accumulator, the first of which may be partially read
+        let held = ((self.position % 8) as u32 + self.buffered) as usize / 8;
+        // the unconsumed bytes are the last ones in the accumulator
+        let read = self.unconsumed.saturating_sub(held);
+        if read > 0 {
+            self.byte_reader.consume(read);
+            self.unconsumed -= read;
+        }
+    }
 
-#[cfg(test)]
-impl InMemoryBitReader {
-    pub fn from_bits(bits: &[Bit]) -> Self {
-        InMemoryBitReader {
-            bits: bits.to_vec(),
-            cursor: 0usize,
+    /// Returns `true` if all bits have been read and the underlying reader is exhausted.
+    pub fn is_at_end(&mut self) -> Result<bool, Error> {
+        Ok(self.buffered == 0 && !self.refill()?)
+    }
+
+    /// Read bytes until the accumulator holds at least `num` bits.
+    fn fill(&mut self, num: u32) -> Result<(), Error> {
+        while self.buffered < num {
+            if !self.refill()? {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
         }
+        Ok(())
+    }
+
+    fn advance(&mut self, num: u32) {
+        self.accumulator = self.accumulator.checked_shl(num).unwrap_or(0);
+        self.buffered -= num;
+        self.position += u64::from(num);
     }
 }
 
-#[cfg(test)]
-impl BitReader for InMemoryBitReader {
-    fn read_bits(&mut self, num: usize) -> Result<Vec<Bit>, ()> {
-        if num + self.cursor > num + self.bits.len() {
-            Err(())
-        } else {
-            let out = self.bits[self.cursor..self.cursor + num].to_vec();
-            self.cursor += num;
-
-            Ok(out)
+impl<T: BufRead> BitReader for BitReaderImpl<T> {
+    fn read_u32(&mut self, num: u32) -> Result<u32, Error> {
+        debug_assert!(num <= 32);
+        if num == 0 {
+            return Ok(0);
+        }
+        self.fill(num)?;
+        let value = (self.accumulator >> (64 - num)) as u32;
+        self.advance(num);
+        Ok(value)
+    }
+
+    fn peek_u32(&mut self, num: u32) -> Result<u32, Error> {
+        debug_assert!(num <= 32);
+        while self.buffered < num {
+            if !self.refill()? {
+                break;
+            }
+        }
+        Ok(self.accumulator.checked_shr(64 - num).unwrap_or(0) as u32)
+    }
+
+    fn consume(&mut self, num: u32) -> Result<(), Error> {
+        if num > self.buffered {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
         }
+        self.advance(num);
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.position
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io::Cursor;
+    use std::io::{BufReader, Cursor};
 
     #[test]
     pub fn reads_0() {
         let vec = vec![0u8, 1, 2, 3];
         let mut cursor = Cursor::new(&vec);
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
-        let bits = reader.read_bits(8);
-        assert_eq!(
-            bits,
-            Ok(vec![
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero
-            ])
-        );
+        assert_eq!(reader.read_u32(8).unwrap(), 0);
     }
 
     #[test]
@@ -137,20 +227,7 @@ mod test {
         let vec = vec![8u8, 1, 2, 3];
         let mut cursor = Cursor::new(&vec);
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
-        let bits = reader.read_bits(8);
-        assert_eq!(
-            bits,
-            Ok(vec![
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::One,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero
-            ])
-        );
+        assert_eq!(reader.read_u32(8).unwrap(), 8);
     }
 
     #[test]
@@ -158,22 +235,8 @@ mod test {
         let vec = vec![8u8, 8u8, 2, 3];
         let mut cursor = Cursor::new(&vec);
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
-        let _ = reader.read_bits(8);
-        let bits = reader.read_bits(8);
-
-        assert_eq!(
-            bits,
-            Ok(vec![
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::One,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero
-            ])
-        );
+        let _ = reader.read_u32(8);
+        assert_eq!(reader.read_u32(8).unwrap(), 8);
     }
 
     #[test]
@@ -181,24 +244,75 @@ mod test {
         let vec = vec![0u8, 255u8, 2, 3];
         let mut cursor = Cursor::new(&vec);
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
-        let bits_1 = reader.read_bits(9);
-        let bits_2 = reader.read_bits(1);
-
-        assert_eq!(
-            bits_1,
-            Ok(vec![
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::One,
-            ])
-        );
-        assert_eq!(bits_2, Ok(vec![Bit::One]));
+        assert_eq!(reader.read_u32(9).unwrap(), 1);
+        assert!(reader.read_bit().unwrap());
+        assert_eq!(reader.position(), 10);
+    }
+
+    #[test]
+    pub fn reads_32_bits_across_bytes() {
+        let vec = vec![0b1010_1010u8, 1, 2, 3, 4, 0xff];
+        let mut cursor = Cursor::new(&vec);
+        let mut reader = BitReaderImpl::from_reader(&mut cursor);
+        assert_eq!(reader.read_u32(3).unwrap(), 0b101);
+        assert_eq!(reader.read_u32(32).unwrap(), 0x5008_1018);
+        assert_eq!(reader.read_u32(13).unwrap(), 0b0_0100_1111_1111);
+        assert!(reader.read_bit().is_err());
+        // only the bytes needed so far have been taken from the reader
+        assert_eq!(cursor.position(), 6);
+    }
+
+    #[test]
+    pub fn reads_across_buffers_of_the_reader() {
+        let vec = (0u8..20).collect::<Vec<_>>();
+        let mut reader = BitReaderImpl::from_reader(BufReader::with_capacity(3, &vec[..]));
+        assert_eq!(reader.read_u32(4).unwrap(), 0);
+        assert_eq!(reader.peek_u32(32).unwrap(), 0x0010_2030);
+        assert_eq!(reader.read_bytes(4).unwrap(), vec![0, 0x10, 0x20, 0x30]);
+        assert_eq!(reader.read_u32(28).unwrap(), 0x0405_0607);
+        let mut rest = vec![];
+        while !reader.is_at_end().unwrap() {
+            rest.push(reader.read_u32(4).unwrap() as u8);
+        }
+        let nibbles = vec[8..].iter().flat_map(|byte| [byte >> 4, byte & 0xf]);
+        assert_eq!(rest, nibbles.collect::<Vec<_>>());
+        assert_eq!(reader.position(), 20 * 8);
+    }
+
+    #[test]
+    pub fn leaves_bytes_not_read_completely() {
+        let vec = vec![1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mut reader = BitReaderImpl::from_reader(&vec[..]);
+        assert_eq!(reader.read_u32(12).unwrap(), 0x010);
+        assert_eq!(reader.peek_u32(32).unwrap(), 0x2030_4050);
+        assert_eq!(reader.into_inner(), &vec[1..]);
+    }
+
+    #[test]
+    pub fn peeks_beyond_end() {
+        let vec = vec![0b1011_0011u8];
+        let mut cursor = Cursor::new(&vec);
+        let mut reader = BitReaderImpl::from_reader(&mut cursor);
+        assert_eq!(reader.peek_u32(4).unwrap(), 0b1011);
+        reader.consume(2).unwrap();
+        assert_eq!(reader.peek_u32(10).unwrap(), 0b11_0011_0000);
+        assert!(reader.consume(7).is_err());
+        reader.consume(6).unwrap();
+        assert_eq!(reader.position(), 8);
+        assert!(reader.is_at_end().unwrap());
+    }
+
+    #[test]
+    pub fn aligns_to_byte() {
+        let vec = vec![0b1010_0000u8, 42u8];
+        let mut cursor = Cursor::new(&vec);
+        let mut reader = BitReaderImpl::from_reader(&mut cursor);
+        let _ = reader.read_u32(3);
+        reader.align_to_byte();
+        assert_eq!(reader.position(), 8);
+        assert!(!reader.is_at_end().unwrap());
+        assert_eq!(reader.read_bytes(1).unwrap(), vec![42]);
+        assert!(reader.is_at_end().unwrap());
     }
 
     #[test]
@@ -206,22 +320,10 @@ mod test {
         let vec = vec![42u8, 42u8, 2, 3];
         let mut cursor = Cursor::new(&vec);
         let mut reader = BitReaderImpl::from_reader(&mut cursor);
-        let _ = reader.read_bits(8);
+        let _ = reader.read_u32(8);
         let bytes = reader.read_bytes(1).unwrap();
-        let bits = reader.read_bits(8).unwrap();
+        let bits = reader.read_u32(8).unwrap();
         assert_eq!(bytes, vec![42]);
-        assert_eq!(
-            bits,
-            vec![
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::One,
-                Bit::Zero,
-            ]
-        );
+        assert_eq!(bits, 2);
     }
 }
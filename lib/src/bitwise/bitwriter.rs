@@ -1,258 +1,166 @@
-use std::{io::Write, iter::repeat};
+use std::io::Write;
 
-use super::Bit;
+use super::BitBuffer;
+use crate::Error;
 
-pub struct BitWriterImpl<'a, T>
+/// Writes bits most significant bit first. Complete bytes are collected and written to the
+/// underlying writer together with the next buffer (see [BitWriter::write_buffer]), on
+/// [BitWriter::finalize] and on [BitWriterImpl::flush], so that the few bits between blocks do
+/// not cause a write each. The bits of a partial byte are kept in the accumulator.
+pub struct BitWriterImpl<T>
 where
     T: Write,
 {
-    pending_bits: Vec<Bit>,
-    byte_writer: &'a mut T,
+    /// The pending bits, aligned to the least significant bit. Fewer than 8 between calls.
+    accumulator: u64,
+    pending: u32,
+    /// Complete bytes not written yet.
+    bytes: Vec<u8>,
+    byte_writer: T,
 }
 
-impl<'a, T> BitWriterImpl<'a, T>
+impl<T> BitWriterImpl<T>
 where
     T: Write,
 {
-    pub fn from_writer(byte_writer: &'a mut T) -> Self {
+    pub fn from_writer(byte_writer: T) -> Self {
         BitWriterImpl {
-            pending_bits: vec![],
+            accumulator: 0,
+            pending: 0,
+            bytes: vec![],
             byte_writer,
         }
     }
-}
 
-pub trait BitWriter {
-    fn write_bits(&mut self, bits_to_write: &[Bit]) -> Result<(), ()>;
-    fn finalize(&mut self) -> Result<(), ()>;
-}
+    pub fn get_ref(&self) -> &T {
+        &self.byte_writer
+    }
 
-impl<W: BitWriter> BitWriter for &mut W {
-    fn write_bits(&mut self, bits_to_write: &[Bit]) -> Result<(), ()> {
-        (**self).write_bits(bits_to_write)
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.byte_writer
     }
 
-    fn finalize(&mut self) -> Result<(), ()> {
-        (**self).finalize()
+    /// Returns the underlying writer. Pending bits and bytes are discarded, hence
+    /// [BitWriter::finalize] should be called first.
+    pub fn into_inner(self) -> T {
+        self.byte_writer
     }
-}
 
-impl<'a, T> BitWriter for BitWriterImpl<'a, T>
-where
-    T: Write,
-{
-    fn write_bits(&mut self, bits_to_write: &[Bit]) -> Result<(), ()> {
-        self.pending_bits.append(&mut bits_to_write.to_vec());
-        let mut chunks = self.pending_bits.chunks_exact(8);
-        for chunk in &mut chunks {
-            let number = convert_to_number(chunk);
-            self.byte_writer.write(&[number as u8]).map_err(|_| ())?;
-        }
-        self.pending_bits = chunks.remainder().to_vec();
+    /// Write the complete bytes and flush the underlying writer. The bits of a partial byte
+    /// are kept.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.write_bytes()?;
+        self.byte_writer.flush()?;
         Ok(())
     }
 
-    fn finalize(&mut self) -> Result<(), ()> {
-        if self.pending_bits.is_empty() {
-            return Ok(());
+    fn write_bytes(&mut self) -> Result<(), Error> {
+        if !self.bytes.is_empty() {
+            self.byte_writer.write_all(&self.bytes)?;
+            self.bytes.clear();
         }
-        let mut trailing_zeros = vec![Bit::Zero; 8 - self.pending_bits.len()];
-        self.pending_bits.append(&mut trailing_zeros);
-        let byte = convert_to_number(&self.pending_bits);
-        self.byte_writer.write(&[byte as u8]).map_err(|_| ())?;
-
         Ok(())
     }
 }
 
-pub fn convert_to_number(input: &[Bit]) -> usize {
-    let mut ct = 0;
-    for digit in input.iter() {
-        ct *= 2;
-        match digit {
-            Bit::Zero => {}
-            Bit::One => ct += 1,
+pub trait BitWriter {
+    /// Write the lowest `num` bits (at most 32) of `value`.
+    fn write_u32(&mut self, value: u32, num: u32) -> Result<(), Error>;
+    fn finalize(&mut self) -> Result<(), Error>;
+    /// Write all bits of the buffer.
+    fn write_buffer(&mut self, buffer: &BitBuffer) -> Result<(), Error> {
+        let full_bytes = (buffer.bit_length() / 8) as usize;
+        for byte in &buffer.bytes()[..full_bytes] {
+            self.write_u32(u32::from(*byte), 8)?;
         }
+        let remainder = (buffer.bit_length() % 8) as u32;
+        if remainder > 0 {
+            self.write_u32(
+                u32::from(buffer.bytes()[full_bytes] >> (8 - remainder)),
+                remainder,
+            )?;
+        }
+        Ok(())
     }
-    ct
 }
 
-pub fn convert_to_code_pad_to_byte(mut input: u8) -> Vec<Bit> {
-    let mut output = Vec::new();
-    let mut counter = 0;
-    while input > 0 {
-        match input & 1 {
-            0 => output.push(Bit::Zero),
-            _ => output.push(Bit::One),
-        }
-        input >>= 1;
-        counter += 1;
+impl<W: BitWriter> BitWriter for &mut W {
+    fn write_u32(&mut self, value: u32, num: u32) -> Result<(), Error> {
+        (**self).write_u32(value, num)
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        (**self).finalize()
     }
-    output.append(
-        &mut repeat(Bit::Zero)
-            .take(8 - counter as usize)
-            .collect::<Vec<_>>(),
-    );
-    output.reverse();
-    output
-}
 
-pub fn convert_to_code_pad_to_bytes(input: &[u8]) -> Vec<Bit> {
-    input
-        .iter()
-        .flat_map(|x| convert_to_code_pad_to_byte(*x))
-        .collect::<Vec<_>>()
+    fn write_buffer(&mut self, buffer: &BitBuffer) -> Result<(), Error> {
+        (**self).write_buffer(buffer)
+    }
 }
 
-pub fn convert_to_code_pad_to_15_bits(mut input: u16) -> Vec<Bit> {
-    let mut output = Vec::new();
-    let mut counter = 0;
-    while input > 0 {
-        match input & 1 {
-            0 => output.push(Bit::Zero),
-            _ => output.push(Bit::One),
+impl<T> BitWriter for BitWriterImpl<T>
+where
+    T: Write,
+{
+    fn write_u32(&mut self, value: u32, num: u32) -> Result<(), Error> {
+        debug_assert!(num <= 32);
+        let mask = (1u64 << num) - 1;
+        self.accumulator = (self.accumulator << num) | (u64::from(value) & mask);
+        self.pending += num;
+        while self.pending >= 8 {
+            self.pending -= 8;
+            self.bytes.push((self.accumulator >> self.pending) as u8);
         }
-        input >>= 1;
-        counter += 1;
+        self.accumulator &= (1 << self.pending) - 1;
+        Ok(())
     }
-    output.append(
-        &mut repeat(Bit::Zero)
-            .take(15 - counter as usize)
-            .collect::<Vec<_>>(),
-    );
-    output.reverse();
-    output
-}
 
-pub fn convert_to_code_pad_to_n_bits(mut input: usize, n: usize) -> Vec<Bit> {
-    let mut output = Vec::new();
-    let mut counter = 0;
-    while input > 0 {
-        match input & 1 {
-            0 => output.push(Bit::Zero),
-            _ => output.push(Bit::One),
+    fn finalize(&mut self) -> Result<(), Error> {
+        if self.pending > 0 {
+            self.bytes
+                .push((self.accumulator << (8 - self.pending)) as u8);
+            self.accumulator = 0;
+            self.pending = 0;
+        }
+        self.write_bytes()
+    }
+
+    /// Writes the complete bytes of the buffer at once, shifting them if the output is not
+    /// aligned to a byte, and the bytes collected before it.
+    fn write_buffer(&mut self, buffer: &BitBuffer) -> Result<(), Error> {
+        let full_bytes = (buffer.bit_length() / 8) as usize;
+        if self.pending == 0 {
+            self.write_bytes()?;
+            self.byte_writer.write_all(&buffer.bytes()[..full_bytes])?;
+        } else {
+            for byte in &buffer.bytes()[..full_bytes] {
+                self.accumulator = (self.accumulator << 8) | u64::from(*byte);
+                self.bytes.push((self.accumulator >> self.pending) as u8);
+                self.accumulator &= (1 << self.pending) - 1;
+            }
+            self.write_bytes()?;
+        }
+        let remainder = (buffer.bit_length() % 8) as u32;
+        if remainder > 0 {
+            self.write_u32(
+                u32::from(buffer.bytes()[full_bytes] >> (8 - remainder)),
+                remainder,
+            )?;
         }
-        input >>= 1;
-        counter += 1;
+        Ok(())
     }
-    output.append(&mut (counter..n).map(|_| Bit::Zero).collect::<Vec<_>>());
-    output.reverse();
-    output
 }
 
-pub fn increment_symbol(input: Vec<Bit>) -> Vec<Bit> {
-    let len = input.len();
-    convert_to_code_pad_to_n_bits(convert_to_number(&input) + 1, len)
-}
 #[cfg(test)]
 mod test {
     use super::*;
 
-    fn convert_to_code(mut input: usize) -> Vec<Bit> {
-        let mut output = Vec::new();
-        while input > 0 {
-            match input & 1 {
-                0 => output.push(Bit::Zero),
-                _ => output.push(Bit::One),
-            }
-            input >>= 1;
-        }
-
-        output.reverse();
-        output
-    }
-
-    #[test]
-    pub fn converts_correctly() {
-        assert_eq!(convert_to_number(&convert_to_code(1)), 1);
-    }
-
-    #[test]
-    pub fn converts_correctly_2() {
-        assert_eq!(convert_to_number(&convert_to_code(3)), 3);
-    }
-
-    #[test]
-    pub fn converts_correctly_more_than_one_byte() {
-        assert_eq!(convert_to_number(&convert_to_code(256)), 256);
-        assert_eq!(
-            convert_to_code(256),
-            vec![
-                Bit::One,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-            ]
-        )
-    }
-
-    #[test]
-    pub fn increments_more_than_one_byte() {
-        assert_eq!(
-            increment_symbol(convert_to_code(255)),
-            vec![
-                Bit::One,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-            ]
-        )
-    }
-
-    #[test]
-    pub fn converts_correctly_3() {
-        assert_eq!(
-            convert_to_code(convert_to_number(&[Bit::One, Bit::Zero])),
-            vec![Bit::One, Bit::Zero]
-        );
-    }
-
-    #[test]
-    pub fn sanity_check() {
-        assert_eq!(
-            convert_to_code_pad_to_byte(3),
-            vec![
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::Zero,
-                Bit::One,
-                Bit::One
-            ]
-        );
-    }
-
-    #[test]
-    pub fn increments_padded() {
-        assert_eq!(
-            increment_symbol(vec![Bit::Zero, Bit::Zero]),
-            vec![Bit::Zero, Bit::One]
-        );
-    }
-
-    #[test]
-    pub fn sanity_check_2() {
-        assert_eq!(convert_to_number(&[Bit::One, Bit::One, Bit::Zero]), 6);
-    }
-
     #[test]
     pub fn writes_bits() {
         let mut buf = vec![];
         {
             let mut bit_writer = BitWriterImpl::from_writer(&mut buf);
-            bit_writer.write_bits(&[Bit::One]).unwrap();
+            bit_writer.write_u32(1, 1).unwrap();
             bit_writer.finalize().unwrap();
         }
         assert_eq!(buf, vec![128]);
@@ -263,18 +171,8 @@ mod test {
         let mut buf = vec![];
         {
             let mut bit_writer = BitWriterImpl::from_writer(&mut buf);
-            bit_writer
-                .write_bits(&[
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                ])
-                .unwrap();
+            bit_writer.write_u32(0xff, 8).unwrap();
+            bit_writer.finalize().unwrap();
         }
 
         assert_eq!(buf, vec![255]);
@@ -287,21 +185,58 @@ mod test {
         {
             let mut bit_writer = BitWriterImpl::from_writer(&mut buf);
 
-            bit_writer
-                .write_bits(&[
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                    Bit::One,
-                ])
-                .unwrap();
+            bit_writer.write_u32(0x1ff, 9).unwrap();
             bit_writer.finalize().unwrap();
         }
         assert_eq!(buf, vec![255, 128]);
     }
+
+    #[test]
+    pub fn writes_32_bits_unaligned() {
+        let mut buf = vec![];
+        {
+            let mut bit_writer = BitWriterImpl::from_writer(&mut buf);
+            bit_writer.write_u32(0b101, 3).unwrap();
+            bit_writer.write_u32(0xabcd_ef01, 32).unwrap();
+            bit_writer.write_u32(1, 1).unwrap();
+            bit_writer.finalize().unwrap();
+        }
+        assert_eq!(buf, vec![0b1011_0101, 0x79, 0xbd, 0xe0, 0x30]);
+    }
+
+    #[test]
+    pub fn collects_bytes_until_flushed() {
+        let mut buf = vec![];
+        let mut bit_writer = BitWriterImpl::from_writer(&mut buf);
+        bit_writer.write_u32(0xabcd, 16).unwrap();
+        bit_writer.write_u32(0b1, 1).unwrap();
+        assert!(bit_writer.get_ref().is_empty());
+        bit_writer.flush().unwrap();
+        assert_eq!(**bit_writer.get_ref(), [0xab, 0xcd]);
+        bit_writer.finalize().unwrap();
+        assert_eq!(buf, vec![0xab, 0xcd, 0x80]);
+    }
+
+    #[test]
+    pub fn writes_buffers() {
+        let mut buffer = BitBuffer::new();
+        buffer.write_u32(0xabcd_ef01, 32);
+        buffer.write_u32(0b11, 2);
+        for offset in 0..8 {
+            let mut direct = vec![];
+            let mut buffered = vec![];
+            {
+                let mut direct_writer = BitWriterImpl::from_writer(&mut direct);
+                let mut buffered_writer = BitWriterImpl::from_writer(&mut buffered);
+                direct_writer.write_u32(0, offset).unwrap();
+                direct_writer.write_u32(0xabcd_ef01, 32).unwrap();
+                direct_writer.write_u32(0b11, 2).unwrap();
+                direct_writer.finalize().unwrap();
+                buffered_writer.write_u32(0, offset).unwrap();
+                buffered_writer.write_buffer(&buffer).unwrap();
+                buffered_writer.finalize().unwrap();
+            }
+            assert_eq!(direct, buffered);
+        }
+    }
 }
@@ -1,8 +1,71 @@
 pub mod bitreader;
 pub mod bitwriter;
 
-#[derive(Clone, PartialEq, Eq, Debug, PartialOrd, Copy)]
-pub enum Bit {
-    Zero,
-    One,
+/// Bits packed into bytes, most significant bit first. The last byte is padded with zeros.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BitBuffer {
+    bytes: Vec<u8>,
+    bit_length: u64,
+}
+
+impl BitBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the lowest `num` bits (at most 32) of `value`.
+    pub fn write_u32(&mut self, value: u32, mut num: u32) {
+        debug_assert!(num <= 32);
+        while num > 0 {
+            let used = (self.bit_length % 8) as u32;
+            if used == 0 {
+                self.bytes.push(0);
+            }
+            let amount = num.min(8 - used);
+            let bits = (value >> (num - amount)) & ((1 << amount) - 1);
+            *self.bytes.last_mut().unwrap() |= (bits << (8 - used - amount)) as u8;
+            num -= amount;
+            self.bit_length += u64::from(amount);
+        }
+    }
+
+    /// Drop all but the first `bit_length` bits.
+    pub fn truncate(&mut self, bit_length: u64) {
+        if bit_length >= self.bit_length {
+            return;
+        }
+        self.bytes.truncate(bit_length.div_ceil(8) as usize);
+        let used = (bit_length % 8) as u32;
+        if used > 0 {
+            *self.bytes.last_mut().unwrap() &= 0xff << (8 - used);
+        }
+        self.bit_length = bit_length;
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bit_length(&self) -> u64 {
+        self.bit_length
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn packs_bits() {
+        let mut buffer = BitBuffer::new();
+        buffer.write_u32(0b101, 3);
+        buffer.write_u32(0xabcd_ef01, 32);
+        buffer.write_u32(1, 1);
+        assert_eq!(buffer.bit_length(), 36);
+        assert_eq!(buffer.bytes(), [0b1011_0101, 0x79, 0xbd, 0xe0, 0x30]);
+
+        buffer.truncate(10);
+        assert_eq!(buffer.bit_length(), 10);
+        assert_eq!(buffer.bytes(), [0b1011_0101, 0x40]);
+    }
 }
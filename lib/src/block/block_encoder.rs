@@ -1,21 +1,15 @@
 use crate::{
-    bitwise::bitwriter::{
-        convert_to_code_pad_to_15_bits, convert_to_code_pad_to_byte, convert_to_code_pad_to_bytes,
-        convert_to_code_pad_to_n_bits,
-    },
-    {
-        bitwise::Bit,
-        block::{
-            bwt::bwt,
-            code_table::encode_code_table,
-            crc32::crc32,
-            huffman::{compute_huffman, HuffmanSymbol},
-            mtf::mtf,
-            rle::rle,
-            selectors::create_selectors,
-            symbol_map::get_symbol_table,
-            zle::zle_transform,
-        },
+    bitwise::BitBuffer,
+    block::{
+        bwt::bwt,
+        code_table::encode_code_table,
+        crc32::crc32,
+        huffman::{compute_huffman, HuffmanSymbol},
+        mtf::mtf,
+        rle::rle,
+        selectors::write_selectors,
+        symbol_map::write_symbol_table,
+        zle::zle_transform,
     },
 };
 
@@ -26,8 +20,8 @@ use super::symbol_statistics::{
 pub(crate) fn generate_block_data(
     input: &[u8],
     encoding_strategy: EncodingStrategy,
-) -> (Vec<Bit>, u32) {
-    let mut output = Vec::<Bit>::new();
+) -> (BitBuffer, u32) {
+    let mut output = BitBuffer::new();
     let checksum = crc32(input);
 
     let rle_data = rle(input);
@@ -59,30 +53,21 @@ pub(crate) fn generate_block_data(
         .into_iter()
         .map(|table| compute_huffman(table).canonicalize())
         .collect::<Vec<_>>();
-    let mut selectors = create_selectors(&selected_tables);
     let num_tables = code_tables.len();
-    let mut symbol_map = get_symbol_table(mtf_data.used_symbols);
-
-    let trees = code_tables
-        .iter()
-        .cloned()
-        .map(|code_table| encode_code_table(code_table))
-        .collect::<Vec<_>>();
 
     // block
-    output.append(&mut block_header(checksum, bwt_data.end_of_string as u32));
-    output.append(&mut symbol_map);
+    write_block_header(checksum, bwt_data.end_of_string, &mut output);
+    write_symbol_table(&mtf_data.used_symbols, &mut output);
 
-    output.append(&mut &mut convert_to_code_pad_to_n_bits(num_tables, 3));
-
-    output.append(&mut convert_to_code_pad_to_15_bits(selectors.1 as u16));
+    output.write_u32(num_tables as u32, 3);
+    output.write_u32(selected_tables.len() as u32, 15);
 
     // the selectors (/)
-    output.append(&mut selectors.0);
+    write_selectors(&selected_tables, &mut output);
 
     // write trees
-    for mut tree in trees.iter().cloned() {
-        output.append(&mut tree)
+    for tree in code_tables.iter().cloned() {
+        encode_code_table(tree, &mut output);
     }
 
     // data
@@ -93,7 +78,7 @@ pub(crate) fn generate_block_data(
 
         for table_entry in code_table.0.iter() {
             if table_entry.symbol == symbol {
-                output.append(&mut table_entry.code.clone());
+                output.write_u32(table_entry.code, u32::from(table_entry.length));
                 break;
             }
         }
@@ -103,45 +88,19 @@ pub(crate) fn generate_block_data(
     let code_table = &code_tables[selected_tables[position / 50] as usize];
 
     // write eob marker
-    output.append(
-        &mut code_table
-            .0
-            .iter()
-            .find(|x| x.symbol == HuffmanSymbol::EoB)
-            .unwrap()
-            .code
-            .clone(),
-    );
-    (output, checksum)
-}
-
-pub(crate) fn block_header(crc: u32, orig_pointer: u32) -> Vec<Bit> {
-    let mut magic: Vec<Bit> = vec![0x31u8, 0x41u8, 0x59u8, 0x26u8, 0x53u8, 0x59u8]
+    let eob = code_table
+        .0
         .iter()
-        .flat_map(|x| convert_to_code_pad_to_byte(*x))
-        .collect::<Vec<_>>();
-    magic.append(&mut convert_to_code_pad_to_bytes(&crc_as_bytes(crc)));
-    magic.push(Bit::Zero); // randomized: false
-
-    magic.append(&mut convert_to_code_pad_to_byte(
-        ((orig_pointer & 0xFF0000) >> 16) as u8,
-    ));
-    magic.append(&mut convert_to_code_pad_to_byte(
-        ((orig_pointer & 0xFF00) >> 8) as u8,
-    ));
-    magic.append(&mut convert_to_code_pad_to_byte(
-        (orig_pointer & 0xFF) as u8,
-    ));
-    magic
+        .find(|x| x.symbol == HuffmanSymbol::EoB)
+        .unwrap();
+    output.write_u32(eob.code, u32::from(eob.length));
+    (output, checksum)
 }
 
-pub fn crc_as_bytes(mut crc: u32) -> Vec<u8> {
-    let mut magic = Vec::new();
-    for _ in 0..4 {
-        magic.push((crc & 0xFF) as u8);
-        crc >>= 8;
-    }
-    magic.reverse();
-
-    magic
+pub(crate) fn write_block_header(crc: u32, orig_pointer: u32, out: &mut BitBuffer) {
+    out.write_u32(0x3141, 16);
+    out.write_u32(0x5926_5359, 32);
+    out.write_u32(crc, 32);
+    out.write_u32(0, 1); // randomized: false
+    out.write_u32(orig_pointer, 24);
 }
@@ -1,7 +1,7 @@
 use super::{duval::rotate_duval, sais::build_suffix_array};
 
 fn bwt_private(string: &[u8]) -> (Vec<u8>, usize) {
-    let (rotated, shift) = rotate_duval(&string);
+    let (rotated, shift) = rotate_duval(string);
 
     let entries = build_suffix_array(&rotated);
     let len = string.len();
@@ -45,6 +45,8 @@ pub struct BwtData {
 #[cfg(test)]
 mod test {
     use super::bwt;
+    use crate::block::bwt::bwt_inverse::inverse_bwt;
+    use crate::test_util::sample_data;
 
     #[test]
     pub fn banana() {
@@ -78,4 +80,14 @@ mod test {
         assert_eq!(bwt_result.data, b"nanbaraaa".to_vec());
         assert_eq!(bwt_result.end_of_string, 5);
     }
+
+    #[test]
+    pub fn inverts_when_last_lyndon_factor_is_not_minimal_rotation() {
+        let input = sample_data(330)[100..].to_vec();
+        let bwt_result = bwt(input.clone());
+        assert_eq!(
+            inverse_bwt(&bwt_result.data, bwt_result.end_of_string as usize),
+            input
+        );
+    }
 }
@@ -1,13 +1,17 @@
+/// Duval's algorithm run over the input concatenated with itself. The start of the last Lyndon
+/// factor beginning in the first copy is the start of the lexicographically minimal rotation.
 fn duval(input: &[u8]) -> usize {
     let mut final_start = 0;
     let n = input.len();
+    let at = |index: usize| input[index % n];
     let mut i = 0;
 
     while i < n {
+        final_start = i;
         let mut j = i + 1;
         let mut k = i;
-        while j < n && input[k] <= input[j] {
-            if input[k] < input[j] {
+        while j < 2 * n && at(k) <= at(j) {
+            if at(k) < at(j) {
                 k = i;
             } else {
                 k += 1;
@@ -15,12 +19,10 @@ fn duval(input: &[u8]) -> usize {
             j += 1;
         }
         while i <= k {
-            final_start = i;
-
             i += j - k;
         }
     }
-    return final_start;
+    final_start
 }
 
 /// Compute lexicographically minimal rotation using the duval algorithm.
@@ -41,12 +43,23 @@ mod test {
     #[test]
     pub fn rotates() {
         let rotated = rotate_duval(b"abacabab");
-        assert_eq!(rotated.0, b"ababacab")
+        assert_eq!(rotated.0, b"abababac")
     }
 
     #[test]
-    pub fn finds_final_lyndon_word() {
+    pub fn finds_minimal_rotation() {
         let rotated = rotate_duval(b"bananaa");
-        assert_eq!(rotated.1, 6)
+        assert_eq!(rotated.1, 5)
+    }
+
+    #[test]
+    pub fn finds_minimal_rotation_not_starting_with_last_lyndon_factor() {
+        let input = b"cabab";
+        let rotated = rotate_duval(input);
+        let minimal = (0..input.len())
+            .map(|i| [&input[i..], &input[..i]].concat())
+            .min()
+            .unwrap();
+        assert_eq!(rotated.0, minimal)
     }
 }
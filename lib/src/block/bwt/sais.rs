@@ -12,10 +12,10 @@ pub(crate) struct SuffixTableEntry {
 /// Build suffix array using the SAIS algorithm
 pub(crate) fn build_suffix_array(bytes: &[u8]) -> Vec<SuffixTableEntry> {
     let data = bytes.iter().map(|x| *x as usize).collect::<Vec<usize>>();
-    return orchestrate_build_suffix_array(&data, u8::MAX as usize + 1)
+    orchestrate_build_suffix_array(&data, u8::MAX as usize + 1)
         .iter()
         .map(|x| SuffixTableEntry { index: x.unwrap() })
-        .collect::<Vec<_>>();
+        .collect::<Vec<_>>()
 }
 
 fn orchestrate_build_suffix_array(text: &[usize], alphabet_size: usize) -> Vec<Option<usize>> {
@@ -98,7 +98,7 @@ fn reduce_problem(
         j += 1;
     }
     ReducedProblem {
-        reduced_text: reduced_text.into_iter().map(|x| x).collect::<Vec<_>>(),
+        reduced_text: reduced_text.into_iter().collect::<Vec<_>>(),
         offsets,
         alphabet_size: current_name + 1,
     }
@@ -227,8 +227,8 @@ fn is_lms_character(index: usize, suffix_types: &[SuffixType]) -> bool {
     if index == 0 {
         return false;
     };
-    return suffix_types.get(index).unwrap() == &SuffixType::S
-        && suffix_types.get(index.checked_sub(1).unwrap()).unwrap() == &SuffixType::L;
+    suffix_types.get(index).unwrap() == &SuffixType::S
+        && suffix_types.get(index.checked_sub(1).unwrap()).unwrap() == &SuffixType::L
 }
 
 fn get_bucket_heads(bucket_sizes: &[usize]) -> Vec<usize> {
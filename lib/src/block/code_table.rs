@@ -1,9 +1,7 @@
 use crate::{
-    bitwise::{
-        bitreader::BitReader,
-        bitwriter::{convert_to_code_pad_to_n_bits, convert_to_number},
-    },
-    {bitwise::Bit, block::delta::DeltaSymbol},
+    bitwise::{bitreader::BitReader, BitBuffer},
+    block::delta::DeltaSymbol,
+    Error,
 };
 
 use super::{
@@ -11,66 +9,54 @@ use super::{
     huffman::CanonicalCodeTable,
 };
 
-pub(crate) fn encode_code_table<T>(table: CanonicalCodeTable<T>) -> Vec<Bit> {
-    let code_lengths = table
-        .0
-        .iter()
-        .map(|entry| entry.code.len() as u8)
-        .collect::<Vec<_>>();
-    encode_bit_lengths(&code_lengths)
+pub(crate) fn encode_code_table<T>(table: CanonicalCodeTable<T>, out: &mut BitBuffer) {
+    let code_lengths = table.0.iter().map(|entry| entry.length).collect::<Vec<_>>();
+    encode_bit_lengths(&code_lengths, out)
 }
 
-fn encode_bit_lengths(code_lengths: &[u8]) -> Vec<Bit> {
-    let mut out = Vec::<Bit>::new();
-
+pub(crate) fn encode_bit_lengths(code_lengths: &[u8], out: &mut BitBuffer) {
     let delta = encode_delta(code_lengths.to_vec());
     match delta {
         DeltaEncoded::Empty => {}
         DeltaEncoded::NonEmpty(enc) => {
-            out.append(&mut convert_to_code_pad_to_n_bits(
-                enc.start_value as usize,
-                5,
-            ));
-            out.append(
-                &mut enc
-                    .deltas
-                    .iter()
-                    .flat_map(|d| match d {
-                        DeltaSymbol::Decrease => {
-                            vec![Bit::One, Bit::One]
-                        }
-                        DeltaSymbol::Increase => {
-                            vec![Bit::One, Bit::Zero]
-                        }
-                        DeltaSymbol::Stop => vec![Bit::Zero],
-                    })
-                    .collect::<Vec<Bit>>(),
-            );
+            out.write_u32(u32::from(enc.start_value), 5);
+            for d in enc.deltas.iter() {
+                match d {
+                    DeltaSymbol::Decrease => out.write_u32(0b11, 2),
+                    DeltaSymbol::Increase => out.write_u32(0b10, 2),
+                    DeltaSymbol::Stop => out.write_u32(0b0, 1),
+                }
+            }
         }
     }
-    out
 }
 
+/// Maximum length of a Huffman code allowed by bzip2.
+pub(crate) const MAX_CODE_LENGTH: usize = 20;
+
 pub(crate) trait ReadDelta {
-    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, ()>;
+    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, Error>;
 }
 
 impl<T> ReadDelta for T
 where
     T: BitReader,
 {
-    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, ()> {
+    fn read_delta(&mut self, amount: usize) -> Result<Vec<u8>, Error> {
         let mut out = vec![];
         let mut read = 0;
 
-        let mut start = convert_to_number(&self.read_bits(5)?);
+        let mut start = self.read_u32(5)? as usize;
         loop {
-            match &self.read_bits(1)?[..] {
-                [Bit::One] => match &self.read_bits(1)?[..] {
-                    [Bit::Zero] => start += 1,
-                    _ => start -= 1,
+            if !(1..=MAX_CODE_LENGTH).contains(&start) {
+                return Err(Error::invalid_huffman_table(self.position()));
+            }
+            match self.read_bit()? {
+                true => match self.read_bit()? {
+                    false => start += 1,
+                    true => start -= 1,
                 },
-                _ => {
+                false => {
                     out.push(start as u8);
                     read += 1;
                 }
@@ -87,26 +73,32 @@ where
 mod test {
     use std::io::Cursor;
 
-    use crate::bitwise::{
-        bitreader::BitReaderImpl,
-        bitwriter::{BitWriter, BitWriterImpl},
-    };
+    use crate::bitwise::bitreader::BitReaderImpl;
 
     use super::*;
 
     #[test]
     pub fn reads_table() {
         let lengths = vec![1, 2, 3, 4];
-        let mut buf = vec![];
-        {
-            let mut writer = BitWriterImpl::from_writer(&mut buf);
-            writer.write_bits(&encode_bit_lengths(&lengths)).unwrap();
-            writer.finalize().unwrap();
-        }
-        let mut cursor = Cursor::new(&buf);
+        let mut buf = BitBuffer::new();
+        encode_bit_lengths(&lengths, &mut buf);
+        let mut cursor = Cursor::new(buf.bytes());
         let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
         let read = bit_reader.read_delta(4);
 
         assert_eq!(lengths, read.unwrap());
     }
+
+    #[test]
+    pub fn rejects_zero_code_length() {
+        let mut buf = BitBuffer::new();
+        encode_bit_lengths(&[1, 0], &mut buf);
+        let mut cursor = Cursor::new(buf.bytes());
+        let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
+
+        assert!(matches!(
+            bit_reader.read_delta(2),
+            Err(Error::InvalidHuffmanTable { .. })
+        ));
+    }
 }
@@ -19,7 +19,7 @@ pub enum DeltaSymbol {
 
 pub fn encode_delta(input: Vec<u8>) -> DeltaEncoded {
     let mut delta_codes = Vec::<DeltaSymbol>::new();
-    let start_value = input.get(0);
+    let start_value = input.first();
     delta_codes.push(DeltaSymbol::Stop);
 
     match start_value {
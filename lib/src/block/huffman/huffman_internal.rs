@@ -1,9 +1,6 @@
-use crate::bitwise::bitwriter::increment_symbol;
-use crate::bitwise::Bit;
-
 use crate::block::symbol_statistics::IntoFrequencyTable;
 use crate::block::zle::ZleSymbol;
-use std::{fmt::Debug, iter::repeat};
+use std::fmt::Debug;
 
 use super::package_merge::compute_lis;
 
@@ -21,7 +18,9 @@ pub(crate) struct CodeTableEntry<T> {
 
 #[derive(PartialEq, Debug, Clone, Eq)]
 pub(crate) struct CanonicalCodeTableEntry<T> {
-    pub code: Vec<Bit>,
+    /// The code in the lowest `length` bits.
+    pub code: u32,
+    pub length: u8,
     pub symbol: T,
 }
 
@@ -70,7 +69,7 @@ pub(crate) fn compute_huffman(
         symbol: HuffmanSymbol::EoB,
     });
 
-    frequency_table.sort_by(|x, y| x.frequency.cmp(&y.frequency));
+    frequency_table.sort_by_key(|x| x.frequency);
 
     // Step 1 break ties between frequencies of symbols
     let mut last: Option<FrequencyTableEntry<_>> = None;
@@ -110,59 +109,25 @@ where
         let mut canonical_code_table_entries = vec![];
 
         self.0.sort();
-        let mut iter = self.0.iter();
-        let code_table_entry = iter.next().unwrap();
-        let mut last = repeat(Bit::Zero)
-            .take(code_table_entry.code)
-            .collect::<Vec<_>>();
-
-        canonical_code_table_entries.push(CanonicalCodeTableEntry {
-            code: last.clone(),
-            symbol: code_table_entry.symbol.clone(),
-        });
-
-        for entry in iter {
-            let mut new_code_unpadded = increment_symbol(last);
-
-            // pad zeros to length
-            let pad = entry.code as isize - new_code_unpadded.len() as isize;
-            for _ in 0..pad {
-                new_code_unpadded.push(Bit::Zero);
-            }
+        let mut code = 0u32;
+        let mut last_length = self.0.first().map_or(0, |entry| entry.code);
+
+        // the codes of each length are consecutive numbers, following the last shorter code
+        for entry in self.0.iter() {
+            code <<= entry.code - last_length;
+            last_length = entry.code;
 
             canonical_code_table_entries.push(CanonicalCodeTableEntry {
-                code: new_code_unpadded.clone(),
+                code,
+                length: entry.code as u8,
                 symbol: entry.symbol.clone(),
             });
-
-            last = new_code_unpadded;
+            code += 1;
         }
         // Sort resulting new codes by symbol
         canonical_code_table_entries.sort_by(|x, y| x.symbol.cmp(&y.symbol));
         CanonicalCodeTable(canonical_code_table_entries)
     }
-
-    pub(crate) fn from_weights(code_lengths: &[u8]) -> CodeTable<HuffmanSymbol<ZleSymbol>> {
-        let mut symbols = vec![
-            HuffmanSymbol::NormalSymbol(ZleSymbol::RunA),
-            HuffmanSymbol::NormalSymbol(ZleSymbol::RunB),
-        ];
-        let mut numbers = (0..code_lengths.len() - 3)
-            .map(|x| HuffmanSymbol::NormalSymbol(ZleSymbol::Number(u8::try_from(x).unwrap() + 1u8)))
-            .collect::<Vec<_>>();
-        symbols.append(&mut numbers);
-        symbols.push(HuffmanSymbol::EoB);
-        CodeTable(
-            symbols
-                .into_iter()
-                .zip(code_lengths.iter())
-                .map(|(symbol, length)| CodeTableEntry {
-                    code: usize::from(*length),
-                    symbol,
-                })
-                .collect::<Vec<_>>(),
-        )
-    }
 }
 
 #[derive(Debug, Clone)]
@@ -179,19 +144,19 @@ mod test {
     pub fn canonicalizes_and_sorts_alphabetically() {
         let table = CodeTable(vec![
             CodeTableEntry {
-                code: vec![Bit::One, Bit::One].len(),
+                code: 2,
                 symbol: 'a',
             },
             CodeTableEntry {
-                code: vec![Bit::Zero].len(),
+                code: 1,
                 symbol: 'b',
             },
             CodeTableEntry {
-                code: vec![Bit::One, Bit::Zero, Bit::One].len(),
+                code: 3,
                 symbol: 'c',
             },
             CodeTableEntry {
-                code: vec![Bit::One, Bit::Zero, Bit::Zero].len(),
+                code: 3,
                 symbol: 'd',
             },
         ]);
@@ -199,19 +164,23 @@ mod test {
         assert_eq!(
             CanonicalCodeTable(vec![
                 CanonicalCodeTableEntry {
-                    code: vec![Bit::One, Bit::Zero],
+                    code: 0b10,
+                    length: 2,
                     symbol: 'a',
                 },
                 CanonicalCodeTableEntry {
-                    code: vec![Bit::Zero],
+                    code: 0b0,
+                    length: 1,
                     symbol: 'b',
                 },
                 CanonicalCodeTableEntry {
-                    code: vec![Bit::One, Bit::One, Bit::Zero],
+                    code: 0b110,
+                    length: 3,
                     symbol: 'c',
                 },
                 CanonicalCodeTableEntry {
-                    code: vec![Bit::One, Bit::One, Bit::One],
+                    code: 0b111,
+                    length: 3,
                     symbol: 'd',
                 },
             ]),
@@ -56,7 +56,7 @@ fn package_merge(coins: &[Coin]) -> Vec<Vec<Coin>> {
     }
 
     for (_, list) in map.iter_mut() {
-        list.sort_by(|x, y| x.numismatic_value.cmp(&y.numismatic_value));
+        list.sort_by_key(|x| x.numismatic_value);
     }
 
     let mut ordered_keys = map.keys().cloned().collect::<Vec<_>>();
@@ -70,7 +70,7 @@ fn package_merge(coins: &[Coin]) -> Vec<Vec<Coin>> {
         let list = map.get_mut(&demonination_exponential).unwrap();
 
         list.append(&mut coins_to_merge);
-        list.sort_by(|x, y| x.numismatic_value.cmp(&y.numismatic_value));
+        list.sort_by_key(|x| x.numismatic_value);
         last = list.to_vec();
         let evens = list
             .iter()
@@ -127,7 +127,6 @@ enum CoinEntryType {
     Two((CoinEntry, CoinEntry)),
 }
 #[cfg(test)]
-
 mod test {
 
     use super::*;
@@ -1,15 +1,106 @@
-use crate::bitwise::{bitreader::BitReader, Bit};
+use crate::bitwise::bitreader::BitReader;
 
+use crate::block::code_table::MAX_CODE_LENGTH;
 use crate::block::zle::ZleSymbol;
+use crate::Error;
 
-use super::{CanonicalCodeTable, HuffmanSymbol};
+/// Number of bits decoded by a single lookup. Longer codes are decoded using `limit`, `base`
+/// and `perm` like in bzip2.
+const LOOKUP_BITS: u32 = 10;
+
+/// Decoding structures of the canonical Huffman code given by the code lengths of a table.
+///
+/// Symbols are numbered like in the code table: RUNA, RUNB, the move-to-front positions
+/// starting at 1 and the end of block.
+pub(crate) struct DecodeTable {
+    /// Symbol and code length for every prefix of `LOOKUP_BITS` bits, length 0 if the code
+    /// is longer.
+    lookup: Vec<(u16, u8)>,
+    /// One more than the largest code of every length.
+    limit: [u32; MAX_CODE_LENGTH + 1],
+    /// Difference between the first code of every length and its index in `perm`.
+    base: [i32; MAX_CODE_LENGTH + 1],
+    /// Symbols ordered by code length, then by symbol.
+    perm: Vec<u16>,
+    max_length: u32,
+    end_of_block: u16,
+}
+
+impl DecodeTable {
+    /// Build the decode table for the code lengths (between 1 and [MAX_CODE_LENGTH]) of all
+    /// symbols. Returns `None` if the lengths do not form a prefix code.
+    pub(crate) fn new(code_lengths: &[u8]) -> Option<Self> {
+        let max_length = u32::from(code_lengths.iter().copied().max()?);
+        let mut perm = (0..code_lengths.len() as u16).collect::<Vec<_>>();
+        perm.sort_by_key(|symbol| code_lengths[usize::from(*symbol)]);
+
+        let mut limit = [0; MAX_CODE_LENGTH + 1];
+        let mut base = [0; MAX_CODE_LENGTH + 1];
+        let mut code = 0u32;
+        let mut index = 0;
+        for length in 1..=max_length as usize {
+            let count = code_lengths
+                .iter()
+                .filter(|code_length| usize::from(**code_length) == length)
+                .count() as u32;
+            base[length] = code as i32 - index as i32;
+            code += count;
+            index += count;
+            if code > 1 << length {
+                return None;
+            }
+            limit[length] = code;
+            code <<= 1;
+        }
+
+        let mut lookup = vec![(0, 0); 1 << LOOKUP_BITS];
+        for (index, symbol) in perm.iter().enumerate() {
+            let length = u32::from(code_lengths[usize::from(*symbol)]);
+            if length > LOOKUP_BITS {
+                break;
+            }
+            let code = (index as i32 + base[length as usize]) as usize;
+            let shift = LOOKUP_BITS - length;
+            for entry in &mut lookup[code << shift..(code + 1) << shift] {
+                *entry = (*symbol, length as u8);
+            }
+        }
+
+        Some(DecodeTable {
+            lookup,
+            limit,
+            base,
+            perm,
+            max_length,
+            end_of_block: code_lengths.len() as u16 - 1,
+        })
+    }
+
+    /// Decode the symbol at the start of the peeked bits, returning the symbol and its code
+    /// length.
+    fn decode(&self, bits: u32) -> Option<(u16, u32)> {
+        let (symbol, length) =
+            self.lookup[(bits >> (MAX_CODE_LENGTH as u32 - LOOKUP_BITS)) as usize];
+        if length > 0 {
+            return Some((symbol, u32::from(length)));
+        }
+        for length in LOOKUP_BITS + 1..=self.max_length {
+            let code = bits >> (MAX_CODE_LENGTH as u32 - length);
+            if code < self.limit[length as usize] {
+                let index = code as i32 - self.base[length as usize];
+                return Some((self.perm[index as usize], length));
+            }
+        }
+        None
+    }
+}
 
 pub(crate) trait ReadSymbols {
     fn read_symbols(
         &mut self,
-        tree: &CanonicalCodeTable<HuffmanSymbol<ZleSymbol>>,
+        table: &DecodeTable,
         max_number: usize,
-    ) -> Result<Vec<ZleSymbol>, ()>;
+    ) -> Result<Vec<ZleSymbol>, Error>;
 }
 
 impl<T> ReadSymbols for T
@@ -18,107 +109,66 @@ where
 {
     fn read_symbols(
         &mut self,
-        table: &CanonicalCodeTable<HuffmanSymbol<ZleSymbol>>,
+        table: &DecodeTable,
         max_number: usize,
-    ) -> Result<Vec<ZleSymbol>, ()> {
+    ) -> Result<Vec<ZleSymbol>, Error> {
         let mut all_symbols = vec![];
-        let mut current_symbol = vec![];
-        let mut symbols_read = 0;
-
-        loop {
-            let mut read = self.read_bits(1)?;
-            current_symbol.append(&mut read);
-            if let Some(symbol) = read_from_table(table, &current_symbol) {
-                match symbol {
-                    HuffmanSymbol::NormalSymbol(symbol) => {
-                        all_symbols.push(symbol);
-                        current_symbol.clear();
-                        symbols_read += 1;
-                    }
-                    HuffmanSymbol::EoB => break,
-                }
-            }
-            if symbols_read == max_number {
-                break;
-            }
-        }
-        Ok(all_symbols)
-    }
-}
 
-fn read_from_table(
-    table: &CanonicalCodeTable<HuffmanSymbol<ZleSymbol>>,
-    symbols: &[Bit],
-) -> Option<HuffmanSymbol<ZleSymbol>> {
-    for entry in table.0.iter() {
-        if symbols == entry.code {
-            return Some(entry.symbol.clone());
+        while all_symbols.len() < max_number {
+            let bits = self.peek_u32(MAX_CODE_LENGTH as u32)?;
+            let (symbol, length) = table
+                .decode(bits)
+                .ok_or_else(|| Error::invalid_huffman_table(self.position()))?;
+            self.consume(length)?;
+            all_symbols.push(match symbol {
+                0 => ZleSymbol::RunA,
+                1 => ZleSymbol::RunB,
+                _ if symbol == table.end_of_block => break,
+                _ => ZleSymbol::Number((symbol - 1) as u8),
+            });
         }
+        Ok(all_symbols)
     }
-    None
 }
 
 #[cfg(test)]
 mod test {
     use std::io::Cursor;
 
-    use crate::bitwise::{
-        bitreader::BitReaderImpl,
-        bitwriter::{BitWriter, BitWriterImpl},
-    };
-
-    use crate::block::huffman::CanonicalCodeTableEntry;
+    use crate::bitwise::{bitreader::BitReaderImpl, BitBuffer};
+    use crate::block::huffman::{CodeTable, CodeTableEntry};
 
     use super::*;
+
+    /// Encode the symbols with the canonical code given by the code lengths.
+    fn encode(code_lengths: &[u8], symbols: &[u16]) -> BitBuffer {
+        let table = CodeTable(
+            code_lengths
+                .iter()
+                .enumerate()
+                .map(|(symbol, length)| CodeTableEntry {
+                    code: usize::from(*length),
+                    symbol: symbol as u16,
+                })
+                .collect(),
+        )
+        .canonicalize();
+        let mut out = BitBuffer::new();
+        for symbol in symbols {
+            let entry = &table.0[usize::from(*symbol)];
+            out.write_u32(entry.code, u32::from(entry.length));
+        }
+        out
+    }
+
     #[test]
     pub fn reads_symbols() {
-        let mut buf = vec![];
-        let table = CanonicalCodeTable(vec![
-            CanonicalCodeTableEntry {
-                code: vec![Bit::Zero],
-                symbol: HuffmanSymbol::NormalSymbol(ZleSymbol::RunA),
-            },
-            CanonicalCodeTableEntry {
-                code: vec![Bit::One, Bit::Zero],
-                symbol: HuffmanSymbol::NormalSymbol(ZleSymbol::RunB),
-            },
-            CanonicalCodeTableEntry {
-                code: vec![Bit::One, Bit::One, Bit::One, Bit::Zero],
-                symbol: HuffmanSymbol::NormalSymbol(ZleSymbol::Number(0)),
-            },
-            CanonicalCodeTableEntry {
-                code: vec![Bit::One, Bit::One, Bit::Zero],
-                symbol: HuffmanSymbol::NormalSymbol(ZleSymbol::Number(1)),
-            },
-            CanonicalCodeTableEntry {
-                code: vec![Bit::One, Bit::One, Bit::One, Bit::One],
-                symbol: HuffmanSymbol::EoB,
-            },
-        ]);
-        let stream = vec![
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::One,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::One,
-            Bit::One,
-            Bit::One,
-        ];
-        {
-            let mut bit_writer = BitWriterImpl::from_writer(&mut buf);
-            bit_writer.write_bits(&stream).unwrap();
-            bit_writer.finalize().unwrap();
-        }
-        let bytes = buf;
+        // RUNA 0, RUNB 10, 2 1110, 1 110, end of block 1111
+        let table = DecodeTable::new(&[1, 2, 4, 3, 4]).unwrap();
+        let mut stream = BitBuffer::new();
+        stream.write_u32(0b01011101101111, 14);
 
-        let mut cursor = Cursor::new(bytes);
+        let mut cursor = Cursor::new(stream.bytes());
 
         let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
 
@@ -127,9 +177,39 @@ mod test {
         let expected_code = vec![
             ZleSymbol::RunA,
             ZleSymbol::RunB,
-            ZleSymbol::Number(0),
             ZleSymbol::Number(1),
+            ZleSymbol::Number(2),
         ];
         assert_eq!(code, expected_code);
+        assert_eq!(bit_reader.position(), 14);
+    }
+
+    #[test]
+    pub fn reads_long_codes() {
+        // lengths 1 to 20 and another code of length 20
+        let code_lengths = (1..=20).chain([20]).collect::<Vec<u8>>();
+        let symbols = [20, 0, 12, 11, 10, 9, 3, 19, 18, 0];
+        let stream = encode(&code_lengths, &symbols);
+        let table = DecodeTable::new(&code_lengths).unwrap();
+        let mut bit_reader = BitReaderImpl::from_reader(Cursor::new(stream.bytes()));
+        let decoded = bit_reader.read_symbols(&table, 50).unwrap();
+        assert_eq!(decoded.len(), 0);
+
+        let stream = encode(&code_lengths, &symbols[1..]);
+        let mut bit_reader = BitReaderImpl::from_reader(Cursor::new(stream.bytes()));
+        let decoded = bit_reader.read_symbols(&table, 8).unwrap();
+        assert_eq!(decoded.len(), 8);
+        assert_eq!(decoded[0], ZleSymbol::RunA);
+        assert_eq!(decoded[1], ZleSymbol::Number(11));
+        assert_eq!(decoded[6], ZleSymbol::Number(18));
+        assert_eq!(bit_reader.position(), stream.bit_length() - 1);
+        // the zeros padding the last byte decode as RUNA until the input ends
+        assert!(bit_reader.read_symbols(&table, 50).is_err());
+    }
+
+    #[test]
+    pub fn rejects_oversubscribed_code() {
+        assert!(DecodeTable::new(&[1, 1, 1]).is_none());
+        assert!(DecodeTable::new(&[1, 2, 3, 3]).is_some());
     }
 }
@@ -6,6 +6,7 @@ mod crc32;
 mod delta;
 mod huffman;
 mod mtf;
+mod randomization;
 mod rle;
 mod selectors;
 mod symbol_map;
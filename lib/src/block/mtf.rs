@@ -1,12 +1,10 @@
-use std::collections::VecDeque;
-
 pub struct MtfData {
     pub encoded: Vec<u8>,
     pub used_symbols: Vec<u8>,
 }
 
 pub fn mtf(mtf_input: &[u8]) -> MtfData {
-    let mut mtf_result = Vec::<u8>::new();
+    let mut mtf_result = Vec::<u8>::with_capacity(mtf_input.len());
     let (mut dict, used_symbols) = create_dict(mtf_input);
 
     for value in mtf_input.iter() {
@@ -21,68 +19,73 @@ pub fn mtf(mtf_input: &[u8]) -> MtfData {
     }
 }
 
-fn create_dict(input: &[u8]) -> (VecDeque<u8>, Vec<u8>) {
-    let mut dict = vec![false; 256];
+/// The used symbols in ascending order, as a dictionary and as a list.
+fn create_dict(input: &[u8]) -> ([u8; 256], Vec<u8>) {
+    let mut used = [false; 256];
     for i in input {
-        dict[*i as usize] = true;
+        used[*i as usize] = true;
     }
-    let used_symbols = dict
+    let used_symbols = used
         .iter()
         .enumerate()
         .filter(|x| *x.1)
         .map(|x| x.0 as u8)
         .collect::<Vec<_>>();
-    let mut dict_vec = used_symbols.clone();
 
-    dict_vec.sort_unstable();
-    (dict_vec.into(), used_symbols)
+    (to_dict(&used_symbols), used_symbols)
 }
 
-fn bring_to_front_of_dict(position: u8, dict: &mut VecDeque<u8>) {
-    let el = dict.remove(usize::from(position)).unwrap();
-    dict.push_front(el);
+/// A move-to-front dictionary starting with the given symbols. Positions after them are unused.
+fn to_dict(symbols: &[u8]) -> [u8; 256] {
+    let mut dict = [0; 256];
+    dict[..symbols.len()].copy_from_slice(symbols);
+    dict
 }
 
-fn find_pos(i: u8, dict: &VecDeque<u8>) -> u8 {
-    let mut position = 0;
-    for dict_element in dict.iter() {
-        if *dict_element == i {
-            return position;
-        }
-        position += 1;
-    }
-    unreachable!()
+/// Move the symbol at the position to the front, shifting the symbols before it back by one.
+/// Returns the symbol.
+fn bring_to_front_of_dict(position: u8, dict: &mut [u8; 256]) -> u8 {
+    let position = usize::from(position);
+    let el = dict[position];
+    dict.copy_within(..position, 1);
+    dict[0] = el;
+    el
+}
+
+fn find_pos(i: u8, dict: &[u8; 256]) -> u8 {
+    dict.iter()
+        .position(|dict_element| *dict_element == i)
+        .unwrap() as u8
 }
 
 pub(crate) fn inverse_mtf(input: &[u8], dictionary: &[u8]) -> Vec<u8> {
-    let mut output = vec![];
-    let mut dictionary: VecDeque<u8> = dictionary.to_vec().into();
+    let mut output = input.to_vec();
+    inverse_mtf_in_place(&mut output, dictionary);
+    output
+}
 
-    for i in input {
-        let value = dictionary[usize::from(*i)];
-        output.push(value);
-        let pos = dictionary
-            .iter()
-            .enumerate()
-            .find(|(_, this_value)| **this_value == value)
-            .unwrap()
-            .0;
-        bring_to_front_of_dict(u8::try_from(pos).unwrap(), &mut dictionary);
+/// Like [inverse_mtf], but replacing the positions with the symbols.
+pub(crate) fn inverse_mtf_in_place(data: &mut [u8], dictionary: &[u8]) {
+    let mut dictionary = to_dict(dictionary);
+    for value in data.iter_mut() {
+        *value = bring_to_front_of_dict(*value, &mut dictionary);
     }
-    output
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::VecDeque;
 
-    use crate::block::mtf::{bring_to_front_of_dict, inverse_mtf, mtf};
+    use crate::block::mtf::{bring_to_front_of_dict, inverse_mtf, mtf, to_dict};
+    use crate::test_util::XorShift;
 
     #[test]
     pub fn brings_to_front_of_dictionary() {
-        let mut dict: VecDeque<u8> = vec![1, 2, 3, 4].into();
-        bring_to_front_of_dict(3, &mut dict);
-        assert_eq!(dict, vec![4, 1, 2, 3]);
+        let mut dict = to_dict(&[1, 2, 3, 4]);
+        assert_eq!(bring_to_front_of_dict(3, &mut dict), 4);
+        assert_eq!(dict[..4], [4, 1, 2, 3]);
+        assert_eq!(bring_to_front_of_dict(0, &mut dict), 4);
+        assert_eq!(dict[..4], [4, 1, 2, 3]);
     }
 
     #[test]
@@ -90,7 +93,7 @@ mod test {
         let input = b"nnbaaaa";
 
         let res: Vec<u8> = vec![2, 0, 2, 2, 0, 0, 0];
-        assert_eq!(mtf(&input.to_vec()).encoded, res);
+        assert_eq!(mtf(input.as_ref()).encoded, res);
     }
 
     #[test]
@@ -100,4 +103,61 @@ mod test {
         let res: Vec<u8> = b"nnbaaaa".to_vec();
         assert_eq!(inverse_mtf(&input, b"abn"), res);
     }
+
+    /// The previous implementation of [mtf], using a `VecDeque` as dictionary.
+    fn mtf_with_deque(input: &[u8]) -> Vec<u8> {
+        let mut used_symbols = input.to_vec();
+        used_symbols.sort_unstable();
+        used_symbols.dedup();
+        let mut dict: VecDeque<u8> = used_symbols.into();
+        input
+            .iter()
+            .map(|value| {
+                let pos = dict.iter().position(|x| x == value).unwrap();
+                let el = dict.remove(pos).unwrap();
+                dict.push_front(el);
+                pos as u8
+            })
+            .collect()
+    }
+
+    /// The previous implementation of [inverse_mtf].
+    fn inverse_mtf_with_deque(input: &[u8], dictionary: &[u8]) -> Vec<u8> {
+        let mut dict: VecDeque<u8> = dictionary.to_vec().into();
+        input
+            .iter()
+            .map(|i| {
+                let el = dict.remove(usize::from(*i)).unwrap();
+                dict.push_front(el);
+                el
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn matches_deque_implementation() {
+        let mut random = XorShift::new(0x1234_5678);
+        for _ in 0..200 {
+            let length = random.next_u32() as usize % 3000;
+            // from few distinct bytes with long runs to all 256 bytes
+            let alphabet = random.next_u32() % 256 + 1;
+            let run = random.next_u32() % 8 + 1;
+            let mut input = vec![];
+            while input.len() < length {
+                let byte = (random.next_u32() % alphabet) as u8;
+                input.extend(std::iter::repeat_n(
+                    byte,
+                    (random.next_u32() % run + 1) as usize,
+                ));
+            }
+
+            let encoded = mtf(&input);
+            assert_eq!(encoded.encoded, mtf_with_deque(&input));
+            assert_eq!(
+                inverse_mtf(&encoded.encoded, &encoded.used_symbols),
+                inverse_mtf_with_deque(&encoded.encoded, &encoded.used_symbols)
+            );
+            assert_eq!(inverse_mtf(&encoded.encoded, &encoded.used_symbols), input);
+        }
+    }
 }
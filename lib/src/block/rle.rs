@@ -1,3 +1,5 @@
+use super::crc32::Crc32;
+
 pub fn rle(input: &[u8]) -> Vec<u8> {
     let mut output = Vec::<u8>::new();
     let mut counter: usize = 0;
@@ -39,33 +41,65 @@ pub fn rle(input: &[u8]) -> Vec<u8> {
     output
 }
 
-pub(crate) fn inverse_rle(input: &[u8]) -> Vec<u8> {
-    let mut output = vec![];
+/// Revert the run-length encoding and compute the CRC of the output while producing it.
+pub(crate) fn inverse_rle(input: impl IntoIterator<Item = u8>) -> (Vec<u8>, u32) {
+    let input = input.into_iter();
+    let mut output = Vec::with_capacity(input.size_hint().0);
+    let mut crc = Crc32::new();
     let mut equal_count = 0;
     let mut previous = None;
     for el in input {
-        if let Some(&previous) = previous {
+        if let Some(previous_byte) = previous {
             if equal_count == 3 {
-                output.append(&mut vec![previous; usize::from(*el)]);
+                let run = [previous_byte; 255];
+                output.extend_from_slice(&run[..usize::from(el)]);
+                crc.update(&run[..usize::from(el)]);
                 equal_count = 0;
-            } else if previous == *el {
+                // the length does not count towards the next run
+                previous = None;
+                continue;
+            } else if previous_byte == el {
                 equal_count += 1;
-                output.push(*el);
             } else {
-                output.push(*el);
                 equal_count = 0;
             }
-        } else {
-            output.push(*el);
         }
+        output.push(el);
+        crc.update_byte(el);
         previous = Some(el);
     }
-    output
+    (output, crc.value())
+}
+
+/// Length of the output of [inverse_rle], without producing it.
+pub(crate) fn inverse_rle_length(input: impl IntoIterator<Item = u8>) -> u64 {
+    let mut length = 0;
+    let mut equal_count = 0;
+    let mut previous = None;
+    for el in input {
+        if let Some(previous_byte) = previous {
+            if equal_count == 3 {
+                length += u64::from(el);
+                equal_count = 0;
+                previous = None;
+                continue;
+            } else if previous_byte == el {
+                equal_count += 1;
+            } else {
+                equal_count = 0;
+            }
+        }
+        length += 1;
+        previous = Some(el);
+    }
+    length
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::block::crc32::crc32;
+
     #[test]
     pub fn keeps_three_byte_sequences() {
         assert_eq!(rle(&[1, 1, 1]), vec![1, 1, 1]);
@@ -115,7 +149,7 @@ mod test {
     #[test]
     pub fn max_block_length() {
         assert_eq!(
-            rle(&std::iter::repeat(3).take(255).collect::<Vec<u8>>()),
+            rle(&std::iter::repeat_n(3, 255).collect::<Vec<u8>>()),
             vec![3, 3, 3, 3, 251]
         );
     }
@@ -123,14 +157,14 @@ mod test {
     #[test]
     pub fn more_than_max_block_length() {
         assert_eq!(
-            rle(&std::iter::repeat(3).take(256).collect::<Vec<u8>>()),
+            rle(&std::iter::repeat_n(3, 256).collect::<Vec<u8>>()),
             vec![3, 3, 3, 3, 251, 3]
         );
     }
     #[test]
     pub fn twice_max_block_length() {
         assert_eq!(
-            rle(&std::iter::repeat(3).take(510).collect::<Vec<u8>>()),
+            rle(&std::iter::repeat_n(3, 510).collect::<Vec<u8>>()),
             vec![3, 3, 3, 3, 251, 3, 3, 3, 3, 251]
         );
     }
@@ -138,7 +172,7 @@ mod test {
     #[test]
     pub fn inverse_rle_works() {
         assert_eq!(
-            inverse_rle(&[1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3]),
+            inverse_rle([1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3]).0,
             vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3]
         );
     }
@@ -146,8 +180,36 @@ mod test {
     #[test]
     pub fn inverse_mixed_sequences_with_and_without_length_2() {
         assert_eq!(
-            inverse_rle(&[1, 1, 1, 1, 0, 2, 2, 2]),
+            inverse_rle([1, 1, 1, 1, 0, 2, 2, 2]).0,
             vec![1, 1, 1, 1, 2, 2, 2]
         );
     }
+
+    #[test]
+    pub fn inverse_rle_starts_new_run_after_length() {
+        // the length 1 is followed by three ones
+        let data = [7, 7, 7, 7, 7, 1, 1, 1, 2];
+        assert_eq!(inverse_rle(rle(&data)).0, data.to_vec());
+    }
+
+    #[test]
+    pub fn inverse_rle_computes_crc() {
+        let data = b"aaaaaaaaaaaabcccc".to_vec();
+        assert_eq!(inverse_rle(rle(&data)), (data.clone(), crc32(&data)));
+    }
+
+    #[test]
+    pub fn inverse_rle_length_matches_output() {
+        for encoded in [
+            rle(b"aaaaaaaaaaaabcccc"),
+            rle(&[7, 7, 7, 7, 7, 7, 7, 7, 7, 1, 1, 1, 1, 1]),
+            rle(&[3; 510]),
+            vec![1, 1, 1, 1, 0, 2, 2, 2],
+        ] {
+            assert_eq!(
+                inverse_rle_length(encoded.clone()),
+                inverse_rle(encoded).0.len() as u64
+            );
+        }
+    }
 }
@@ -1,42 +1,39 @@
-use crate::bitwise::{bitreader::BitReader, Bit};
+use crate::bitwise::{bitreader::BitReader, BitBuffer};
+use crate::Error;
 
 use super::mtf::mtf;
 
-pub(crate) fn create_selectors(selectors: &[u8]) -> (Vec<Bit>, usize) {
-    let mut out = vec![];
-    let selectors_mtf = mtf(&selectors);
+/// Write the move-to-front encoded selectors in unary code.
+pub(crate) fn write_selectors(selectors: &[u8], out: &mut BitBuffer) {
+    let selectors_mtf = mtf(selectors);
 
     for selector in selectors_mtf.encoded {
-        for _ in 0..selector as usize {
-            out.push(Bit::One);
-        }
-        out.push(Bit::Zero);
+        // `selector` ones followed by a zero
+        let selector = u32::from(selector);
+        out.write_u32(((1 << selector) - 1) << 1, selector + 1);
     }
-    let l = selectors.len();
-    (out, l)
 }
 
 pub(crate) trait ReadUnary {
-    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, ()>;
+    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, Error>;
 }
 
 impl<T> ReadUnary for T
 where
     T: BitReader,
 {
-    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, ()> {
+    fn read_unary(&mut self, amount: usize) -> Result<Vec<u8>, Error> {
         let mut output = vec![];
         let mut current_symbol = 0u8;
         let mut symbol_count = 0;
         loop {
-            match &self.read_bits(1)?[..] {
-                [Bit::One] => current_symbol += 1,
-                [Bit::Zero] => {
+            match self.read_bit()? {
+                true => current_symbol += 1,
+                false => {
                     symbol_count += 1;
                     output.push(current_symbol);
                     current_symbol = 0;
                 }
-                _ => return Err(()),
             }
             if symbol_count >= amount {
                 break;
@@ -49,41 +46,35 @@ where
 #[cfg(test)]
 mod test {
 
-    use crate::bitwise::bitreader::InMemoryBitReader;
+    use std::io::Cursor;
+
+    use crate::bitwise::bitreader::BitReaderImpl;
 
     use super::*;
 
     #[test]
     pub fn decodes_one_unary() {
-        let encoded = vec![
-            Bit::One,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-        ];
+        let encoded = [0b1101_1010u8];
 
-        let mut bit_reader = InMemoryBitReader::from_bits(&encoded);
+        let mut bit_reader = BitReaderImpl::from_reader(Cursor::new(encoded));
         assert_eq!(bit_reader.read_unary(1).unwrap(), vec![2]);
     }
 
     #[test]
     pub fn decodes_zero_unary() {
-        let encoded = vec![
-            Bit::One,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::One,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-        ];
-        let mut bit_reader = InMemoryBitReader::from_bits(&encoded);
+        let encoded = [0b1001_1010u8];
+        let mut bit_reader = BitReaderImpl::from_reader(Cursor::new(encoded));
 
         assert_eq!(bit_reader.read_unary(4).unwrap(), vec![1, 0, 2, 1]);
     }
+
+    #[test]
+    pub fn roundtrips_selectors() {
+        let selectors = [0u8, 0, 1, 5, 2, 2, 0];
+        let mut out = BitBuffer::new();
+        write_selectors(&selectors, &mut out);
+        let mut bit_reader = BitReaderImpl::from_reader(Cursor::new(out.bytes()));
+        let encoded = bit_reader.read_unary(selectors.len()).unwrap();
+        assert_eq!(encoded, mtf(&selectors).encoded);
+    }
 }
@@ -1,270 +1,127 @@
-use crate::bitwise::{bitreader::BitReader, Bit};
-
-pub(crate) fn get_symbol_table(table: Vec<u8>) -> Vec<Bit> {
-    let mut table2 = table.clone();
-    table2.sort_unstable();
-    let mut out_region = Vec::new();
-    let mut out_detail = Vec::new();
-
-    let mut used_symbols_details: [Option<[Bit; 16]>; 16] = [None; 16];
-    let mut table2 = table.clone();
-    table2.sort_unstable();
+use crate::bitwise::{bitreader::BitReader, BitBuffer};
+use crate::Error;
 
+/// Returns the bitmap of the used ranges of 16 bytes and the bitmaps of the used bytes within
+/// every used range, most significant bit first.
+fn symbol_bitmaps(table: &[u8]) -> (u16, Vec<u16>) {
+    let mut used_symbols_details = [0u16; 16];
     for value in table.iter() {
-        let table_number = (value / 16) as usize;
-        let entry_in_table = (value % 16) as usize;
-
-        match &mut used_symbols_details[table_number] {
-            Some(given) => given[entry_in_table] = Bit::One,
-            None => {
-                let mut detail_at_position = [Bit::Zero; 16];
-                detail_at_position[entry_in_table] = Bit::One;
-                used_symbols_details[table_number] = Some(detail_at_position)
-            }
-        }
+        used_symbols_details[usize::from(value / 16)] |= 0x8000 >> (value % 16);
     }
-
-    for (_, used) in used_symbols_details.iter().enumerate() {
-        match used {
-            Some(detail) => {
-                out_region.push(Bit::One);
-                for a in detail.iter() {
-                    out_detail.push(*a);
-                }
-            }
-            None => {
-                out_region.push(Bit::Zero);
-            }
+    let mut regions = 0u16;
+    let mut details = vec![];
+    for (region, detail) in used_symbols_details.iter().enumerate() {
+        if *detail != 0 {
+            regions |= 0x8000 >> region;
+            details.push(*detail);
         }
     }
-    out_region.append(&mut out_detail);
-
-    out_region
+    (regions, details)
 }
 
-fn get_used_regions(input: &[Bit]) -> Vec<u8> {
-    let mut out = vec![];
-    for (count, bit) in input.iter().enumerate() {
-        if matches!(bit, Bit::One) {
-            out.push(count.try_into().unwrap());
-        }
+pub(crate) fn write_symbol_table(table: &[u8], out: &mut BitBuffer) {
+    let (regions, details) = symbol_bitmaps(table);
+    out.write_u32(u32::from(regions), 16);
+    for detail in details {
+        out.write_u32(u32::from(detail), 16);
     }
-    out
 }
 
-fn get_used_symbols_from_regions(regions: &[u8], input: Vec<Bit>) -> Vec<u8> {
+fn get_used_regions(input: u16) -> Vec<u8> {
+    (0..16u8)
+        .filter(|region| input & (0x8000 >> region) != 0)
+        .collect()
+}
+
+fn get_used_symbols_from_regions(regions: &[u8], input: &[u16]) -> Vec<u8> {
     regions
         .iter()
-        .flat_map(|x| vec![x; 16].into_iter().enumerate())
         .zip(input)
-        .flat_map(|((position, region), used)| match used {
-            Bit::Zero => vec![],
-            Bit::One => vec![region * 16 + u8::try_from(position).unwrap()],
+        .flat_map(|(region, detail)| {
+            (0..16u8)
+                .filter(move |position| detail & (0x8000 >> position) != 0)
+                .map(move |position| region * 16 + position)
         })
         .collect::<Vec<u8>>()
 }
 
 pub(crate) trait GetSymbolTable {
-    fn get_symbol_table(&mut self) -> Result<Vec<u8>, ()>;
+    fn get_symbol_table(&mut self) -> Result<Vec<u8>, Error>;
 }
 
 impl<T> GetSymbolTable for T
 where
     T: BitReader,
 {
-    fn get_symbol_table(&mut self) -> Result<Vec<u8>, ()> {
-        let index = self.read_bits(16)?;
-        let used_regions = get_used_regions(&index);
-        let regions = self.read_bits(16 * used_regions.len())?;
-        Ok(get_used_symbols_from_regions(&used_regions, regions))
+    fn get_symbol_table(&mut self) -> Result<Vec<u8>, Error> {
+        let index = self.read_u32(16)? as u16;
+        let used_regions = get_used_regions(index);
+        let mut regions = vec![];
+        for _ in 0..used_regions.len() {
+            regions.push(self.read_u32(16)? as u16);
+        }
+        Ok(get_used_symbols_from_regions(&used_regions, &regions))
     }
 }
 
 #[cfg(test)]
-
 mod test {
-    use std::iter::repeat;
 
     use super::*;
     #[test]
     pub fn one_symbol() {
-        let out = &get_symbol_table(vec![0]);
-        let mut expected = vec![Bit::One];
-
-        expected.append(&mut zeros(15));
-        assert_eq!(&out[0..16], expected);
-        assert_eq!(&out[16..32], expected);
-        assert_eq!(out.len(), 32);
+        assert_eq!(symbol_bitmaps(&[0]), (0x8000, vec![0x8000]));
     }
 
     #[test]
     pub fn two_symbols_in_same_range() {
-        let out = &get_symbol_table(vec![0, 1]);
-        let mut expected = vec![Bit::One];
-
-        expected.append(&mut zeros(15));
-        assert_eq!(&out[0..16], expected);
-
-        let mut expected = vec![Bit::One, Bit::One];
-        expected.append(&mut zeros(14));
-
-        assert_eq!(&out[16..32], expected);
-        assert_eq!(out.len(), 32);
+        assert_eq!(symbol_bitmaps(&[0, 1]), (0x8000, vec![0xc000]));
     }
 
     #[test]
     pub fn two_symbols_in_different_ranges() {
-        let out = &get_symbol_table(vec![0, 16]);
-        let mut expected_overview = vec![Bit::One, Bit::One];
-
-        expected_overview.append(&mut zeros(14));
-        assert_eq!(&out[0..16], expected_overview);
-
-        let mut expected_detail = vec![Bit::One];
-        expected_detail.append(&mut zeros(15));
-
-        assert_eq!(&out[16..32], expected_detail);
-        assert_eq!(&out[32..48], expected_detail);
-
-        assert_eq!(out.len(), 48);
+        assert_eq!(symbol_bitmaps(&[0, 16]), (0xc000, vec![0x8000, 0x8000]));
     }
 
     #[test]
     pub fn two_symbols_in_non_neighboured_ranges() {
-        let out = &get_symbol_table(vec![0, 32]);
-        let mut expected_overview = vec![Bit::One, Bit::Zero, Bit::One];
-
-        expected_overview.append(&mut zeros(13));
-        assert_eq!(&out[0..16], expected_overview);
-
-        let mut expected_detail = vec![Bit::One];
-        expected_detail.append(&mut zeros(15));
-
-        assert_eq!(&out[16..32], expected_detail);
-        assert_eq!(&out[32..48], expected_detail);
-
-        assert_eq!(out.len(), 48);
+        assert_eq!(symbol_bitmaps(&[0, 32]), (0xa000, vec![0x8000, 0x8000]));
     }
 
-    fn zeros(num: usize) -> Vec<Bit> {
-        repeat(Bit::Zero).take(num).collect::<Vec<_>>()
+    #[test]
+    pub fn writes_table() {
+        let mut out = BitBuffer::new();
+        write_symbol_table(&[0, 32], &mut out);
+        assert_eq!(out.bit_length(), 48);
+        assert_eq!(out.bytes(), [0xa0, 0, 0x80, 0, 0x80, 0]);
     }
 
     #[test]
     pub fn decodes() {
-        let bit_pattern = vec![
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::One,
-        ];
-
-        assert_eq!(get_used_regions(&bit_pattern), vec![1, 3, 15]);
+        assert_eq!(get_used_regions(0b0101_0000_0000_0001), vec![1, 3, 15]);
     }
 
     #[test]
     pub fn computes_used_symbol() {
-        let bit_pattern = vec![
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-        ];
-        let used_symbols = get_used_symbols_from_regions(&[1], bit_pattern);
+        let used_symbols = get_used_symbols_from_regions(&[1], &[0b0100_0000_0000_0000]);
         assert_eq!(used_symbols, vec![17]);
     }
 
     #[test]
     pub fn computes_more_used_symbols() {
-        let mut bit_pattern = vec![
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-        ];
-        bit_pattern.append(&mut bit_pattern.clone());
-        let used_symbols = get_used_symbols_from_regions(&[1, 2], bit_pattern);
+        let bit_pattern = 0b0100_0000_0000_0000;
+        let used_symbols = get_used_symbols_from_regions(&[1, 2], &[bit_pattern, bit_pattern]);
         assert_eq!(used_symbols, vec![17, 33]);
     }
 
     #[test]
     pub fn computes_even_more_used_symbols() {
-        let mut bit_pattern = vec![
-            Bit::Zero,
-            Bit::One,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-        ];
-        let mut second_bit_pattern = vec![
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::Zero,
-            Bit::One,
-        ];
-        bit_pattern.append(&mut bit_pattern.clone());
-        second_bit_pattern.append(&mut bit_pattern);
-        let used_symbols = get_used_symbols_from_regions(&[0, 1, 2], second_bit_pattern);
+        let bit_pattern = 0b0100_0000_0000_0000;
+        let second_bit_pattern = 0b0000_0000_0000_0001;
+        let used_symbols = get_used_symbols_from_regions(
+            &[0, 1, 2],
+            &[second_bit_pattern, bit_pattern, bit_pattern],
+        );
         assert_eq!(used_symbols, vec![15, 17, 33]);
     }
 }
@@ -122,7 +122,7 @@ impl SymbolReporter for BlockWisePropabilityMap {
                 .means
                 .iter()
                 .map(|x| IntoFrequencyTable {
-                    frequencies: x.iter().map(|x| *x as usize).collect::<Vec<_>>(),
+                    frequencies: x.to_vec(),
                 })
                 .collect::<Vec<_>>(),
             selectors: tables.assignments,
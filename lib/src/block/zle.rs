@@ -11,23 +11,7 @@ pub(crate) enum ZleSymbol {
 
 impl PartialOrd for ZleSymbol {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self {
-            ZleSymbol::RunA => match other {
-                ZleSymbol::RunA => Some(Ordering::Equal),
-                ZleSymbol::RunB => Some(Ordering::Less),
-                ZleSymbol::Number(_) => Some(Ordering::Less),
-            },
-            ZleSymbol::RunB => match other {
-                ZleSymbol::RunA => Some(Ordering::Greater),
-                ZleSymbol::RunB => Some(Ordering::Equal),
-                ZleSymbol::Number(_) => Some(Ordering::Less),
-            },
-            ZleSymbol::Number(this_number) => match other {
-                ZleSymbol::RunA => Some(Ordering::Greater),
-                ZleSymbol::RunB => Some(Ordering::Greater),
-                ZleSymbol::Number(other_number) => this_number.partial_cmp(other_number),
-            },
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -108,6 +92,35 @@ pub(crate) fn zle_transform(
     (zle_result, symbol_reporter.finalize())
 }
 
+/// Number of bytes the symbols decode to, or `None` if it exceeds `max_length`. Runs of
+/// damaged data could otherwise claim more zeros than fit into memory, or into a `usize`.
+pub(crate) fn decoded_length(input: &[ZleSymbol], max_length: usize) -> Option<usize> {
+    let mut length = 0;
+    // value of the next run symbol in the bijective base-2 numeral of the current run
+    let mut weight = 1;
+    for symbol in input {
+        match symbol {
+            ZleSymbol::RunA => {
+                length += weight;
+                weight <<= 1;
+            }
+            ZleSymbol::RunB => {
+                length += 2 * weight;
+                weight <<= 1;
+            }
+            ZleSymbol::Number(_) => {
+                length += 1;
+                weight = 1;
+            }
+        }
+        if length > max_length {
+            return None;
+        }
+    }
+    Some(length)
+}
+
+/// The length of the output has to be checked with [decoded_length] first.
 pub(crate) fn decode_zle(input: &[ZleSymbol]) -> Vec<u8> {
     let mut output = vec![];
     let mut zeros = vec![];
@@ -148,7 +161,6 @@ fn decode_zero_amount(input: &[ZleSymbol]) -> usize {
 }
 
 #[cfg(test)]
-
 mod test {
 
     use crate::block::symbol_statistics::SinglePropabilityMap;
@@ -187,9 +199,25 @@ mod test {
         for (num, encoded) in data.into_iter() {
             let zeroes = decode_zero_amount(&encoded);
             assert_eq!(zeroes, num);
+            assert_eq!(decoded_length(&encoded, 100), Some(num));
         }
     }
 
+    #[test]
+    pub fn bounds_decoded_length() {
+        let input = [
+            ZleSymbol::RunB,
+            ZleSymbol::Number(3),
+            ZleSymbol::RunA,
+            ZleSymbol::RunA,
+        ];
+        assert_eq!(decoded_length(&input, 10), Some(decode_zle(&input).len()));
+        assert_eq!(decoded_length(&input, 6), Some(6));
+        assert_eq!(decoded_length(&input, 5), None);
+        // would overflow a usize when decoded
+        assert_eq!(decoded_length(&vec![ZleSymbol::RunB; 70], 900_000), None);
+    }
+
     #[test]
     pub fn encodes_zero_amount() {
         let data = vec![
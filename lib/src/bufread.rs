@@ -0,0 +1,246 @@
+//! Compressing and decompressing readers on top of a [BufRead], mirroring `bzip2::bufread`.
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::stream::{Bz2Decoder, Bz2Encoder, DecodeOptions};
+use crate::{Compression, EncodingStrategy};
+
+/// A reader returning the compressed contents of the inner reader.
+pub struct BzEncoder<R> {
+    reader: R,
+    encoder: Option<Bz2Encoder<Vec<u8>>>,
+    output: Vec<u8>,
+    position: usize,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl<R: BufRead> BzEncoder<R> {
+    pub fn new(reader: R, level: Compression) -> Self {
+        BzEncoder {
+            reader,
+            encoder: Some(Bz2Encoder::new(
+                vec![],
+                0,
+                EncodingStrategy::Single,
+                level.into(),
+            )),
+            output: vec![],
+            position: 0,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+}
+
+impl<R> BzEncoder<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Number of uncompressed bytes read from the inner reader.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of compressed bytes returned.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl<R: BufRead> Read for BzEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.output.len() {
+            let encoder = match self.encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => return Ok(0),
+            };
+            let input = self.reader.fill_buf()?;
+            if input.is_empty() {
+                self.output = self.encoder.take().unwrap().finish()?;
+            } else {
+                let amount = encoder.write(input)?;
+                self.reader.consume(amount);
+                self.total_in += amount as u64;
+                self.output = std::mem::take(encoder.get_mut());
+            }
+            self.position = 0;
+        }
+        let available = &self.output[self.position..];
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.position += amount;
+        self.total_out += amount as u64;
+        Ok(amount)
+    }
+}
+
+/// A reader decompressing a single bzip2 stream read from the inner reader. Data following
+/// the stream is left unread, see [MultiBzDecoder] for decoding concatenated streams.
+pub struct BzDecoder<R: BufRead> {
+    decoder: Bz2Decoder<R>,
+    total_out: u64,
+}
+
+impl<R: BufRead> BzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, DecodeOptions::default())
+    }
+
+    /// Decode with the given options, e.g. [DecodeOptions::small_memory]. A single stream is
+    /// decoded regardless of [DecodeOptions::multi_stream].
+    pub fn with_options(reader: R, options: DecodeOptions) -> Self {
+        let options = DecodeOptions {
+            multi_stream: false,
+            ..options
+        };
+        BzDecoder {
+            decoder: Bz2Decoder::with_options(reader, options),
+            total_out: 0,
+        }
+    }
+}
+
+impl<R: BufRead> BzDecoder<R> {
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.decoder.get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.decoder.into_inner()
+    }
+
+    /// Number of compressed bytes read from the inner reader.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.total_in()
+    }
+
+    /// Number of decompressed bytes returned.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl<R: BufRead> Read for BzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = self.decoder.read(buf)?;
+        self.total_out += amount as u64;
+        Ok(amount)
+    }
+}
+
+/// A reader decompressing all concatenated bzip2 streams read from the inner reader.
+pub struct MultiBzDecoder<R: BufRead>(BzDecoder<R>);
+
+impl<R: BufRead> MultiBzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        MultiBzDecoder(BzDecoder {
+            decoder: Bz2Decoder::new(reader),
+            total_out: 0,
+        })
+    }
+}
+
+impl<R: BufRead> MultiBzDecoder<R> {
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.0.get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.0.into_inner()
+    }
+
+    /// Number of compressed bytes read from the inner reader.
+    pub fn total_in(&self) -> u64 {
+        self.0.total_in()
+    }
+
+    /// Number of decompressed bytes returned.
+    pub fn total_out(&self) -> u64 {
+        self.0.total_out()
+    }
+}
+
+impl<R: BufRead> Read for MultiBzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn roundtrips() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(100);
+        let mut encoder = BzEncoder::new(&data[..], Compression::default());
+        let mut compressed = vec![];
+        encoder.read_to_end(&mut compressed).unwrap();
+        assert_eq!(&compressed[..4], b"BZh6");
+        assert_eq!(encoder.total_in(), data.len() as u64);
+        assert_eq!(encoder.total_out(), compressed.len() as u64);
+
+        let mut decoder = BzDecoder::new(&compressed[..]);
+        let mut decompressed = vec![];
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(decoder.total_in(), compressed.len() as u64);
+        assert_eq!(decoder.total_out(), data.len() as u64);
+    }
+
+    #[test]
+    pub fn decodes_single_or_all_streams() {
+        let mut compressed = vec![];
+        BzEncoder::new(&b"first "[..], Compression::fast())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        BzEncoder::new(&b"second"[..], Compression::fast())
+            .read_to_end(&mut compressed)
+            .unwrap();
+
+        let mut decompressed = vec![];
+        BzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, b"first ");
+
+        let mut decompressed = vec![];
+        MultiBzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, b"first second");
+    }
+
+    #[test]
+    pub fn leaves_following_data_unread() {
+        let mut compressed = vec![];
+        BzEncoder::new(&b"first "[..], Compression::fast())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        compressed.extend_from_slice(b"following");
+
+        let mut decoder = BzDecoder::new(&compressed[..]);
+        let mut decompressed = vec![];
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, b"first ");
+        assert_eq!(decoder.into_inner(), b"following");
+    }
+}
@@ -0,0 +1,54 @@
+use crate::Level;
+
+/// Compression level as used by the `bzip2` crate, see [Level] for its meaning.
+///
+/// Levels outside of 1 to 9 are clamped to that range when compressing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression(u32);
+
+impl Compression {
+    pub fn new(level: u32) -> Compression {
+        Compression(level)
+    }
+
+    /// Level 1 with a block size of 100k.
+    pub fn fast() -> Compression {
+        Compression(1)
+    }
+
+    /// Level 9 with a block size of 900k.
+    pub fn best() -> Compression {
+        Compression(9)
+    }
+
+    pub fn level(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Level 6, as in the `bzip2` crate.
+impl Default for Compression {
+    fn default() -> Self {
+        Compression(6)
+    }
+}
+
+impl From<Compression> for Level {
+    fn from(compression: Compression) -> Self {
+        Level::new(compression.0.clamp(1, 9) as u8).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn converts_to_level() {
+        assert_eq!(Level::from(Compression::best()), Level::best());
+        assert_eq!(Level::from(Compression::fast()), Level::fastest());
+        assert_eq!(Level::from(Compression::default()).value(), 6);
+        assert_eq!(Level::from(Compression::new(0)), Level::fastest());
+        assert_eq!(Level::from(Compression::new(42)), Level::best());
+    }
+}
@@ -0,0 +1,155 @@
+use std::fmt::Display;
+use std::io;
+
+/// Errors occurring while reading or writing bzip2 streams.
+///
+/// Errors located in a block carry the index of the block (starting at 0, counting across
+/// concatenated streams) and an offset in bits from the start of the compressed input. For invalid Huffman data
+/// this is the position at which the problem was detected, otherwise the start of the block.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying reader or writer failed, including unexpected ends of the input.
+    Io(io::Error),
+    /// The stream does not start with the magic `BZh` followed by a level digit.
+    BadMagic,
+    /// Neither a block header nor the stream footer was found, or a block header field is invalid.
+    BadBlockHeader { block: usize, bit_offset: u64 },
+    /// The code tables, selectors or Huffman coded symbols of a block are invalid.
+    InvalidHuffmanTable { block: usize, bit_offset: u64 },
+    /// The checksum of the decoded block differs from the one stored in the block header.
+    BlockCrcMismatch {
+        block: usize,
+        bit_offset: u64,
+        stored: u32,
+        computed: u32,
+    },
+    /// The combined checksum of all blocks differs from the one stored in the stream footer.
+    StreamCrcMismatch { stored: u32, computed: u32 },
+}
+
+impl Error {
+    /// The block index is attached later using [Error::in_block].
+    pub(crate) fn invalid_huffman_table(bit_offset: u64) -> Self {
+        Error::InvalidHuffmanTable {
+            block: 0,
+            bit_offset,
+        }
+    }
+
+    /// Attaches the block index to errors raised while decoding a block.
+    pub(crate) fn in_block(self, index: usize) -> Self {
+        match self {
+            Error::BadBlockHeader { bit_offset, .. } => Error::BadBlockHeader {
+                block: index,
+                bit_offset,
+            },
+            Error::InvalidHuffmanTable { bit_offset, .. } => Error::InvalidHuffmanTable {
+                block: index,
+                bit_offset,
+            },
+            Error::BlockCrcMismatch {
+                bit_offset,
+                stored,
+                computed,
+                ..
+            } => Error::BlockCrcMismatch {
+                block: index,
+                bit_offset,
+                stored,
+                computed,
+            },
+            other => other,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {}", error),
+            Error::BadMagic => write!(f, "not a bzip2 stream"),
+            Error::BadBlockHeader { block, bit_offset } => write!(
+                f,
+                "invalid block header in block {} at bit {}",
+                block, bit_offset
+            ),
+            Error::InvalidHuffmanTable { block, bit_offset } => write!(
+                f,
+                "invalid Huffman coded data in block {} at bit {}",
+                block, bit_offset
+            ),
+            Error::BlockCrcMismatch {
+                block,
+                bit_offset,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "CRC mismatch in block {} at bit {} (stored {:#010x}, computed {:#010x})",
+                block, bit_offset, stored, computed
+            ),
+            Error::StreamCrcMismatch { stored, computed } => write!(
+                f,
+                "stream CRC mismatch (stored {:#010x}, computed {:#010x})",
+                stored, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Used by the [std::io::Read] and [std::io::Write] adapters. I/O errors are passed through,
+/// everything else is reported as [io::ErrorKind::InvalidData].
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => error,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn attaches_block_index() {
+        let error = Error::InvalidHuffmanTable {
+            block: 0,
+            bit_offset: 100,
+        }
+        .in_block(3);
+        assert!(matches!(
+            error,
+            Error::InvalidHuffmanTable {
+                block: 3,
+                bit_offset: 100
+            }
+        ));
+    }
+
+    #[test]
+    pub fn converts_to_io_error() {
+        let error: io::Error = Error::BadMagic.into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error: io::Error = Error::Io(io::ErrorKind::UnexpectedEof.into()).into();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
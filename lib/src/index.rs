@@ -0,0 +1,260 @@
+//! Block indexes for random access into bzip2 files.
+//!
+//! Blocks can be decoded independently of each other, given their position in the compressed
+//! input. A [BlockIndex] records this position for every block, together with the range of the
+//! uncompressed data it contains. It is created by decoding a file once with
+//! [BlockIndex::scan], or while compressing with [crate::stream::encode_stream] and
+//! [crate::stream::Bz2Encoder::index], and can be stored next to the file as `file.bz2.idx`.
+//!
+//! The sidecar format starts with the magic `BZIX` and a version byte, followed by the number
+//! of blocks and, for every block, the distance in bits to the previous block, the uncompressed
+//! length (all LEB128 encoded) and the CRC (4 bytes big endian).
+
+use std::ffi::OsString;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::stream::{Bz2Decoder, DecodeOptions};
+use crate::Error;
+
+const SIDECAR_MAGIC: &[u8; 4] = b"BZIX";
+const SIDECAR_VERSION: u8 = 1;
+
+/// Position and content of a single block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexedBlock {
+    /// Offset of the block magic in bits from the start of the compressed input.
+    pub bit_offset: u64,
+    /// Offset of the first byte of the block in the uncompressed data.
+    pub uncompressed_start: u64,
+    /// Number of uncompressed bytes in the block.
+    pub uncompressed_length: u64,
+    /// CRC of the uncompressed bytes, as stored in the block header.
+    pub crc: u32,
+}
+
+impl IndexedBlock {
+    /// Offset of the first uncompressed byte after the block.
+    pub fn uncompressed_end(&self) -> u64 {
+        self.uncompressed_start + self.uncompressed_length
+    }
+}
+
+/// The blocks of a bzip2 file in order, across concatenated streams.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    blocks: Vec<IndexedBlock>,
+}
+
+impl BlockIndex {
+    /// Decode the input and record all of its blocks. Blocks are decoded on `num_threads`
+    /// worker threads, see [DecodeOptions::num_threads].
+    pub fn scan(reader: impl Read, num_threads: usize) -> Result<Self, Error> {
+        let options = DecodeOptions {
+            num_threads,
+            ..Default::default()
+        };
+        let mut decoder = Bz2Decoder::with_options(BufReader::new(reader), options);
+        let mut index = BlockIndex::default();
+        while let Some(length) = decoder.next_block()?.map(|block| block.len() as u64) {
+            let (bit_offset, crc) = decoder.last_block().unwrap();
+            index.push(bit_offset, length, crc);
+        }
+        Ok(index)
+    }
+
+    /// Append a block following the previous one in the uncompressed data.
+    pub(crate) fn push(&mut self, bit_offset: u64, uncompressed_length: u64, crc: u32) {
+        let uncompressed_start = self.uncompressed_size();
+        self.blocks.push(IndexedBlock {
+            bit_offset,
+            uncompressed_start,
+            uncompressed_length,
+            crc,
+        });
+    }
+
+    pub fn blocks(&self) -> &[IndexedBlock] {
+        &self.blocks
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Total length of the uncompressed data.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.blocks.last().map_or(0, IndexedBlock::uncompressed_end)
+    }
+
+    /// Returns the index of the block containing the given uncompressed offset, `None` if the
+    /// offset is beyond the end of the data.
+    pub fn find(&self, offset: u64) -> Option<usize> {
+        let position = self
+            .blocks
+            .partition_point(|block| block.uncompressed_end() <= offset);
+        (position < self.blocks.len()).then_some(position)
+    }
+
+    /// Path of the sidecar file for a compressed file, i.e. `file.bz2.idx` for `file.bz2`.
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut sidecar = OsString::from(path.as_os_str());
+        sidecar.push(".idx");
+        PathBuf::from(sidecar)
+    }
+
+    /// Write the index in the sidecar format.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut out = SIDECAR_MAGIC.to_vec();
+        out.push(SIDECAR_VERSION);
+        write_varint(&mut out, self.blocks.len() as u64);
+        let mut previous_offset = 0;
+        for block in &self.blocks {
+            write_varint(&mut out, block.bit_offset - previous_offset);
+            write_varint(&mut out, block.uncompressed_length);
+            out.extend_from_slice(&block.crc.to_be_bytes());
+            previous_offset = block.bit_offset;
+        }
+        writer.write_all(&out)
+    }
+
+    /// Read an index in the sidecar format. Invalid data is reported as
+    /// [io::ErrorKind::InvalidData].
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != SIDECAR_MAGIC || header[4] != SIDECAR_VERSION {
+            return Err(invalid_data("not a block index"));
+        }
+        let count = read_varint(&mut reader)?;
+        let mut index = BlockIndex::default();
+        let mut bit_offset = 0u64;
+        for _ in 0..count {
+            bit_offset = bit_offset
+                .checked_add(read_varint(&mut reader)?)
+                .ok_or_else(|| invalid_data("block offset out of range"))?;
+            let length = read_varint(&mut reader)?;
+            if index.uncompressed_size().checked_add(length).is_none() {
+                return Err(invalid_data("block length out of range"));
+            }
+            let mut crc = [0u8; 4];
+            reader.read_exact(&mut crc)?;
+            index.push(bit_offset, length, u32::from_be_bytes(crc));
+        }
+        Ok(index)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(mut reader: impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("number out of range"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::{encode_stream, Bz2Encoder, Level};
+    use crate::test_util::sample_data;
+    use crate::EncodingStrategy;
+
+    #[test]
+    pub fn scans_blocks() {
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, Level::best());
+        encoder.write_all(b"first block, ").unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(b"second block").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let index = BlockIndex::scan(&compressed[..], 0).unwrap();
+        let recovered = crate::recover::recover_blocks(&compressed[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(index.len(), 2);
+        for (block, recovered) in index.blocks().iter().zip(recovered.iter()) {
+            assert_eq!(block.bit_offset, recovered.bit_offset);
+            assert_eq!(block.crc, recovered.crc);
+        }
+        assert_eq!(index.blocks()[1].uncompressed_start, 13);
+        assert_eq!(index.uncompressed_size(), 25);
+    }
+
+    #[test]
+    pub fn encoder_emits_index() {
+        let level = Level::new(1).unwrap();
+        let data = sample_data(5 * level.block_size() / 2);
+        for num_threads in [0, 2] {
+            let mut compressed = vec![];
+            let index = encode_stream(
+                &data[..],
+                &mut compressed,
+                num_threads,
+                EncodingStrategy::Single,
+                level,
+            )
+            .unwrap();
+            assert_eq!(index.len(), 3);
+            assert_eq!(index, BlockIndex::scan(&compressed[..], 0).unwrap());
+        }
+    }
+
+    #[test]
+    pub fn finds_blocks() {
+        let mut index = BlockIndex::default();
+        index.push(32, 10, 0);
+        index.push(100, 5, 0);
+        assert_eq!(index.find(0), Some(0));
+        assert_eq!(index.find(9), Some(0));
+        assert_eq!(index.find(10), Some(1));
+        assert_eq!(index.find(14), Some(1));
+        assert_eq!(index.find(15), None);
+    }
+
+    #[test]
+    pub fn roundtrips_sidecar() {
+        let mut index = BlockIndex::default();
+        index.push(32, 900_000, 0xdead_beef);
+        index.push(5_000_000_000, 1, 7);
+        let mut sidecar = vec![];
+        index.write_to(&mut sidecar).unwrap();
+        assert_eq!(BlockIndex::read_from(&sidecar[..]).unwrap(), index);
+
+        let error = BlockIndex::read_from(&b"BZh91AY&SY"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut index = BlockIndex::default();
+        index.push(32, u64::MAX, 0);
+        index.push(64, u64::MAX, 0);
+        let mut sidecar = vec![];
+        index.write_to(&mut sidecar).unwrap();
+        let error = BlockIndex::read_from(&sidecar[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            BlockIndex::sidecar_path(Path::new("dir/file.bz2")),
+            PathBuf::from("dir/file.bz2.idx")
+        );
+    }
+}
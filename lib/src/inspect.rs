@@ -0,0 +1,211 @@
+//! Inspecting the structure of bzip2 streams, e.g. to debug files from other producers.
+
+use std::io::{BufReader, Read};
+
+use crate::bitwise::bitreader::{BitReader, BitReaderImpl};
+use crate::block::block_decoder::read_block;
+use crate::stream::{what_next, BlockType, Level, StreamWalker};
+use crate::Error;
+
+/// How much of each block is decoded by [inspect].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InspectMode {
+    /// Only read the block header, the code tables and the Huffman coded symbols.
+    Headers,
+    /// Decode the block as well to determine its size and check its CRC.
+    FullDecode,
+}
+
+/// The content of a block header and its code tables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Index of the block, counting across concatenated streams.
+    pub index: usize,
+    /// Index of the stream containing the block.
+    pub stream: usize,
+    /// Level stored in the header of the stream.
+    pub level: Level,
+    /// Offset of the block magic in bits from the start of the input.
+    pub bit_offset: u64,
+    /// Length of the block in bits, including the block magic.
+    pub bit_length: u64,
+    /// CRC stored in the block header.
+    pub crc: u32,
+    pub randomized: bool,
+    /// Position of the original data in the sorted rotations of the Burrows-Wheeler transform.
+    pub orig_ptr: usize,
+    /// The byte values occurring in the block.
+    pub used_symbols: Vec<u8>,
+    pub num_tables: usize,
+    pub num_selectors: usize,
+    /// Code length of every symbol for every Huffman table.
+    pub code_lengths: Vec<Vec<u8>>,
+    /// Size of the decoded block, only with [InspectMode::FullDecode].
+    pub uncompressed_size: Option<u64>,
+    /// Whether the decoded data matches the stored CRC, only with [InspectMode::FullDecode].
+    pub crc_valid: Option<bool>,
+}
+
+/// The end of a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub level: Level,
+    /// Number of blocks in the stream.
+    pub num_blocks: usize,
+    /// Combined CRC stored in the stream footer.
+    pub crc: u32,
+}
+
+/// Iterator over the blocks of all concatenated streams of the input, see [inspect].
+pub struct InspectedBlocks<R: Read> {
+    bit_reader: BitReaderImpl<BufReader<R>>,
+    mode: InspectMode,
+    walker: StreamWalker,
+    next_index: usize,
+    streams: Vec<StreamInfo>,
+    stream_blocks: usize,
+}
+
+/// Walk the blocks of the input. Errors end the iteration.
+pub fn inspect<R: Read>(reader: R, mode: InspectMode) -> InspectedBlocks<R> {
+    InspectedBlocks {
+        bit_reader: BitReaderImpl::from_reader(BufReader::new(reader)),
+        mode,
+        walker: StreamWalker::new(true),
+        next_index: 0,
+        streams: vec![],
+        stream_blocks: 0,
+    }
+}
+
+impl<R: Read> InspectedBlocks<R> {
+    /// The streams whose footer has been read so far.
+    pub fn streams(&self) -> &[StreamInfo] {
+        &self.streams
+    }
+
+    fn next_block(&mut self) -> Result<Option<BlockInfo>, Error> {
+        while self.walker.seek_blocks(&mut self.bit_reader)? {
+            match what_next(&mut self.bit_reader)
+                .map_err(|error| error.in_block(self.next_index))?
+            {
+                BlockType::StreamFooter => {
+                    let crc = self.walker.read_footer(&mut self.bit_reader)?;
+                    self.streams.push(StreamInfo {
+                        level: self.walker.level(),
+                        num_blocks: self.stream_blocks,
+                        crc,
+                    });
+                    self.stream_blocks = 0;
+                }
+                BlockType::BlockHeader => {
+                    let index = self.next_index;
+                    self.next_index += 1;
+                    self.stream_blocks += 1;
+                    return self
+                        .read_block_info(index)
+                        .map(Some)
+                        .map_err(|error| error.in_block(index));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_block_info(&mut self, index: usize) -> Result<BlockInfo, Error> {
+        let block = read_block(&mut self.bit_reader, self.walker.level())?;
+        let mut info = BlockInfo {
+            index,
+            stream: self.streams.len(),
+            level: self.walker.level(),
+            bit_offset: block.block_start,
+            bit_length: self.bit_reader.position() - block.block_start,
+            crc: block.crc,
+            randomized: block.randomized,
+            orig_ptr: block.orig_ptr,
+            used_symbols: block.symbols.clone(),
+            num_tables: block.code_lengths.len(),
+            num_selectors: block.num_selectors,
+            code_lengths: block.code_lengths.clone(),
+            uncompressed_size: None,
+            crc_valid: None,
+        };
+        if self.mode == InspectMode::FullDecode {
+            let decoded = block.decode(false)?;
+            info.uncompressed_size = Some(decoded.data.len() as u64);
+            info.crc_valid = Some(decoded.crc_error().is_none());
+        }
+        Ok(info)
+    }
+}
+
+impl<R: Read> Iterator for InspectedBlocks<R> {
+    type Item = Result<BlockInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_block().transpose();
+        if matches!(result, Some(Err(_))) {
+            self.walker.finish();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::Bz2Encoder;
+    use crate::EncodingStrategy;
+    use std::io::Write;
+
+    fn compress(blocks: &[&[u8]], level: Level) -> Vec<u8> {
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, level);
+        for block in blocks {
+            encoder.write_all(block).unwrap();
+            encoder.flush().unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    pub fn reports_block_headers() {
+        let mut compressed = compress(&[b"abracadabra", b"banana"], Level::best());
+        compressed.append(&mut compress(&[b"zzz"], Level::new(3).unwrap()));
+        let mut blocks = inspect(&compressed[..], InspectMode::Headers);
+        let infos = blocks.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(infos.len(), 3);
+
+        let first = &infos[0];
+        assert_eq!(first.bit_offset, 32);
+        assert_eq!(infos[1].bit_offset, first.bit_offset + first.bit_length);
+        assert_eq!(first.used_symbols, b"abcdr");
+        assert!(!first.randomized);
+        assert_eq!(first.code_lengths.len(), first.num_tables);
+        // the alphabet contains RUNA, RUNB, the symbols but the first and the end of block
+        assert_eq!(first.code_lengths[0].len(), 5 + 2);
+        assert_eq!(first.uncompressed_size, None);
+
+        assert_eq!(infos[2].stream, 1);
+        assert_eq!(infos[2].level, Level::new(3).unwrap());
+        assert_eq!(blocks.streams().len(), 2);
+        assert_eq!(blocks.streams()[0].num_blocks, 2);
+    }
+
+    #[test]
+    pub fn decodes_blocks() {
+        let mut compressed = compress(&[b"abracadabra", b"banana"], Level::best());
+        let infos = inspect(&compressed[..], InspectMode::FullDecode)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(infos[0].uncompressed_size, Some(11));
+        assert_eq!(infos[1].crc_valid, Some(true));
+
+        // flip the lowest bit of the stored CRC of the first block
+        compressed[4 + 6 + 3] ^= 1;
+        let first = inspect(&compressed[..], InspectMode::FullDecode)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.crc_valid, Some(false));
+    }
+}
@@ -4,8 +4,31 @@
 //! The main interfaces are
 //!
 //!  * [stream::encode_stream]
-//!  * [stream::decode_stream]
+//!  * [stream::decode_stream] (see [stream::decode_stream_with_options] for [stream::DecodeOptions])
+//!  * [stream::Bz2Encoder] for compressing data as it is produced
+//!  * [stream::Bz2Decoder] for reading decompressed data incrementally
+//!  * [recover::recover_blocks] for salvaging intact blocks from damaged files
+//!  * [index::BlockIndex] for locating blocks in the compressed input
+//!  * [seekable::SeekableBz2Reader] for reading at arbitrary offsets of the uncompressed data
+//!  * [inspect::inspect] for examining the headers and code tables of all blocks
+//!
+//! The modules [read], [write] and [bufread] together with [Compression] mirror the API of the
+//! `bzip2` crate.
 mod bitwise;
 mod block;
+pub mod bufread;
+mod compression;
+mod error;
+pub mod index;
+pub mod inspect;
+pub mod read;
+pub mod recover;
+pub mod seekable;
 pub mod stream;
+#[cfg(test)]
+mod test_util;
+pub mod write;
 pub use block::symbol_statistics::EncodingStrategy;
+pub use compression::Compression;
+pub use error::Error;
+pub use stream::Level;
@@ -0,0 +1,148 @@
+//! Compressing and decompressing readers, mirroring `bzip2::read`.
+//!
+//! These wrap the inner reader in a [BufReader], hence data following a stream may have been
+//! consumed from the inner reader.
+
+use std::io::{self, BufReader, Read};
+
+use crate::stream::DecodeOptions;
+use crate::{bufread, Compression};
+
+/// A reader returning the compressed contents of the inner reader.
+pub struct BzEncoder<R>(bufread::BzEncoder<BufReader<R>>);
+
+impl<R: Read> BzEncoder<R> {
+    pub fn new(reader: R, level: Compression) -> Self {
+        BzEncoder(bufread::BzEncoder::new(BufReader::new(reader), level))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref().get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.0.get_mut().get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.0.into_inner().into_inner()
+    }
+
+    /// Number of uncompressed bytes read from the inner reader.
+    pub fn total_in(&self) -> u64 {
+        self.0.total_in()
+    }
+
+    /// Number of compressed bytes returned.
+    pub fn total_out(&self) -> u64 {
+        self.0.total_out()
+    }
+}
+
+impl<R: Read> Read for BzEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+/// A reader decompressing a single bzip2 stream read from the inner reader.
+pub struct BzDecoder<R: Read>(bufread::BzDecoder<BufReader<R>>);
+
+impl<R: Read> BzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        BzDecoder(bufread::BzDecoder::new(BufReader::new(reader)))
+    }
+
+    /// See [bufread::BzDecoder::with_options].
+    pub fn with_options(reader: R, options: DecodeOptions) -> Self {
+        BzDecoder(bufread::BzDecoder::with_options(
+            BufReader::new(reader),
+            options,
+        ))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref().get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.0.get_mut().get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.0.into_inner().into_inner()
+    }
+
+    /// Number of compressed bytes consumed.
+    pub fn total_in(&self) -> u64 {
+        self.0.total_in()
+    }
+
+    /// Number of decompressed bytes returned.
+    pub fn total_out(&self) -> u64 {
+        self.0.total_out()
+    }
+}
+
+impl<R: Read> Read for BzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+/// A reader decompressing all concatenated bzip2 streams read from the inner reader.
+pub struct MultiBzDecoder<R: Read>(bufread::MultiBzDecoder<BufReader<R>>);
+
+impl<R: Read> MultiBzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        MultiBzDecoder(bufread::MultiBzDecoder::new(BufReader::new(reader)))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref().get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.0.get_mut().get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.0.into_inner().into_inner()
+    }
+
+    /// Number of compressed bytes consumed.
+    pub fn total_in(&self) -> u64 {
+        self.0.total_in()
+    }
+
+    /// Number of decompressed bytes returned.
+    pub fn total_out(&self) -> u64 {
+        self.0.total_out()
+    }
+}
+
+impl<R: Read> Read for MultiBzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn roundtrips() {
+        let data = (0..100_000).map(|x| (x % 7) as u8).collect::<Vec<_>>();
+        let mut compressed = vec![];
+        BzEncoder::new(&data[..], Compression::best())
+            .read_to_end(&mut compressed)
+            .unwrap();
+
+        let mut decompressed = vec![];
+        MultiBzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
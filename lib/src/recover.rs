@@ -0,0 +1,268 @@
+//! Salvaging intact blocks from damaged files, like `bzip2recover`.
+//!
+//! Blocks are found by scanning every bit offset of the input for the block magic and the
+//! stream footer magic, so damaged headers or block contents do not affect other blocks. Each
+//! block is re-wrapped as a stream of its own and checked by decoding it.
+
+use std::io::{self, BufReader, Read};
+
+use crate::bitwise::bitreader::{BitReader, BitReaderImpl};
+use crate::bitwise::bitwriter::{BitWriter, BitWriterImpl};
+use crate::bitwise::BitBuffer;
+use crate::stream::{decode_stream, write_file_header, write_stream_footer, Level};
+use crate::Error;
+
+pub(crate) const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+pub(crate) const FOOTER_MAGIC: u64 = 0x1772_4538_5090;
+pub(crate) const MAGIC_BITS: usize = 48;
+pub(crate) const MAGIC_MASK: u64 = (1 << MAGIC_BITS) - 1;
+
+/// A block found in the input, re-wrapped as a stream of its own.
+#[derive(Debug)]
+pub struct RecoveredBlock {
+    /// Index of the block in the input, starting at 0.
+    pub index: usize,
+    /// Offset of the block magic in bits from the start of the input.
+    pub bit_offset: u64,
+    /// Length of the block in bits, including the block magic.
+    pub bit_length: u64,
+    /// CRC stored in the block header.
+    pub crc: u32,
+    /// A complete bzip2 stream containing only this block.
+    pub stream: Vec<u8>,
+    /// The error decoding the stream, `None` if the block is intact.
+    pub error: Option<Error>,
+}
+
+impl RecoveredBlock {
+    pub fn is_intact(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Iterator over the blocks found in the input, see [recover_blocks].
+pub struct RecoveredBlocks<R: Read> {
+    bit_reader: BitReaderImpl<BufReader<R>>,
+    window: u64,
+    /// Start and bits (without magic) of the current block.
+    block: Option<(u64, BitBuffer)>,
+    next_index: usize,
+    finished: bool,
+}
+
+/// Scan the input for blocks. Every block ends at the next block magic or footer magic, or at
+/// the end of the input. Only I/O errors are returned as errors, damaged blocks are reported
+/// in [RecoveredBlock::error].
+pub fn recover_blocks<R: Read>(reader: R) -> RecoveredBlocks<R> {
+    RecoveredBlocks {
+        bit_reader: BitReaderImpl::from_reader(BufReader::new(reader)),
+        window: 0,
+        block: None,
+        next_index: 0,
+        finished: false,
+    }
+}
+
+impl<R: Read> RecoveredBlocks<R> {
+    fn next_block(&mut self) -> Result<Option<RecoveredBlock>, Error> {
+        while !self.finished {
+            if self.bit_reader.is_at_end()? {
+                self.finished = true;
+                return Ok(self.end_block(0, None));
+            }
+            let bit = self.bit_reader.read_u32(1)?;
+            self.window = ((self.window << 1) | u64::from(bit)) & MAGIC_MASK;
+            if let Some((_, bits)) = self.block.as_mut() {
+                bits.write_u32(bit, 1);
+            }
+            let next_block = match self.window {
+                BLOCK_MAGIC => Some(self.bit_reader.position() - MAGIC_BITS as u64),
+                FOOTER_MAGIC => None,
+                _ => continue,
+            };
+            if let Some(recovered) = self.end_block(MAGIC_BITS, next_block) {
+                return Ok(Some(recovered));
+            }
+        }
+        Ok(None)
+    }
+
+    /// End the current block, dropping the given number of trailing magic bits, and start the
+    /// next one if there is any.
+    fn end_block(&mut self, magic_bits: usize, next_block: Option<u64>) -> Option<RecoveredBlock> {
+        let ended = self.block.take();
+        self.block = next_block.map(|start| (start, BitBuffer::new()));
+        let (bit_offset, mut bits) = ended?;
+        bits.truncate(bits.bit_length().saturating_sub(magic_bits as u64));
+        let index = self.next_index;
+        self.next_index += 1;
+        Some(rewrap_block(index, bit_offset, &bits))
+    }
+}
+
+impl<R: Read> Iterator for RecoveredBlocks<R> {
+    type Item = Result<RecoveredBlock, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().transpose()
+    }
+}
+
+fn rewrap_block(index: usize, bit_offset: u64, bits: &BitBuffer) -> RecoveredBlock {
+    let crc = match bits.bytes() {
+        [a, b, c, d, ..] if bits.bit_length() >= 32 => u32::from_be_bytes([*a, *b, *c, *d]),
+        _ => 0,
+    };
+    let mut stream = vec![];
+    let mut bit_writer = BitWriterImpl::from_writer(&mut stream);
+    // writing into a vector does not fail
+    let _ = write_file_header(&mut bit_writer, Level::best())
+        .and_then(|_| bit_writer.write_u32((BLOCK_MAGIC >> 32) as u32, 16))
+        .and_then(|_| bit_writer.write_u32(BLOCK_MAGIC as u32, 32))
+        .and_then(|_| bit_writer.write_buffer(bits))
+        // the stream CRC of a single block is the block CRC
+        .and_then(|_| write_stream_footer(&mut bit_writer, crc))
+        .and_then(|_| bit_writer.finalize());
+    let error = decode_stream(&stream[..], io::sink(), 0)
+        .err()
+        .map(|error| locate(error, index, bit_offset));
+    RecoveredBlock {
+        index,
+        bit_offset,
+        bit_length: MAGIC_BITS as u64 + bits.bit_length(),
+        crc,
+        stream,
+        error,
+    }
+}
+
+/// Errors refer to the re-wrapped stream, translate them to the block's position in the input.
+fn locate(error: Error, index: usize, block_offset: u64) -> Error {
+    // the block starts after the stream header
+    let shift = |bit_offset: u64| bit_offset + block_offset - 32;
+    match error.in_block(index) {
+        Error::BadBlockHeader { block, bit_offset } => Error::BadBlockHeader {
+            block,
+            bit_offset: shift(bit_offset),
+        },
+        Error::InvalidHuffmanTable { block, bit_offset } => Error::InvalidHuffmanTable {
+            block,
+            bit_offset: shift(bit_offset),
+        },
+        Error::BlockCrcMismatch {
+            block,
+            bit_offset,
+            stored,
+            computed,
+        } => Error::BlockCrcMismatch {
+            block,
+            bit_offset: shift(bit_offset),
+            stored,
+            computed,
+        },
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::Bz2Encoder;
+    use crate::EncodingStrategy;
+    use std::io::Write;
+
+    fn compress(blocks: &[&[u8]]) -> Vec<u8> {
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, Level::best());
+        for block in blocks {
+            encoder.write_all(block).unwrap();
+            encoder.flush().unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(stream: &[u8]) -> Vec<u8> {
+        let mut decompressed = vec![];
+        decode_stream(stream, &mut decompressed, 0).unwrap();
+        decompressed
+    }
+
+    #[test]
+    pub fn recovers_all_blocks() {
+        let blocks: [&[u8]; 3] = [b"first block, ", b"second block, ", b"third block"];
+        let compressed = compress(&blocks);
+        let recovered = recover_blocks(&compressed[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(recovered.len(), 3);
+        assert_eq!(recovered[0].bit_offset, 32);
+        for (block, expected) in recovered.iter().zip(blocks.iter()) {
+            assert!(block.is_intact());
+            assert_eq!(&decompress(&block.stream), expected);
+        }
+        assert_eq!(
+            recovered[1].bit_offset,
+            recovered[0].bit_offset + recovered[0].bit_length
+        );
+    }
+
+    #[test]
+    pub fn reports_damaged_block() {
+        let blocks: [&[u8]; 3] = [b"first block, ", b"second block, ", b"third block"];
+        let mut compressed = compress(&blocks);
+        let recovered = recover_blocks(&compressed[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // damage the data of the second block
+        let byte = ((recovered[1].bit_offset + recovered[1].bit_length / 2) / 8) as usize;
+        compressed[byte] ^= 0x10;
+
+        let recovered = recover_blocks(&compressed[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(recovered.len(), 3);
+        assert!(recovered[0].is_intact());
+        assert!(!recovered[1].is_intact());
+        assert!(recovered[2].is_intact());
+        assert_eq!(decompress(&recovered[2].stream), blocks[2]);
+    }
+
+    #[test]
+    pub fn salvages_blocks_around_garbage_block() {
+        // the garbage block claims a run of zeros longer than any block
+        let compressed =
+            crate::stream::stream_with_overlong_block(b"first block, ", b"third block");
+        let recovered = recover_blocks(&compressed[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(recovered.len(), 3);
+        assert!(matches!(
+            recovered[1].error,
+            Some(Error::InvalidHuffmanTable { block: 1, .. })
+        ));
+        assert!(recovered[0].is_intact());
+        assert!(recovered[2].is_intact());
+        assert_eq!(decompress(&recovered[0].stream), b"first block, ");
+        assert_eq!(decompress(&recovered[2].stream), b"third block");
+    }
+
+    #[test]
+    pub fn locates_errors_in_input() {
+        let blocks: [&[u8]; 2] = [b"first block, ", b"second block"];
+        let mut compressed = compress(&blocks);
+        let second = recover_blocks(&compressed[..]).nth(1).unwrap().unwrap();
+        // flip the lowest bit of the stored CRC
+        let crc_end = second.bit_offset + 48 + 32 - 1;
+        compressed[(crc_end / 8) as usize] ^= 0x80 >> (crc_end % 8);
+
+        let second = recover_blocks(&compressed[..]).nth(1).unwrap().unwrap();
+        assert!(matches!(
+            second.error,
+            Some(Error::BlockCrcMismatch { block: 1, bit_offset, .. }) if bit_offset == second.bit_offset
+        ));
+    }
+
+    #[test]
+    pub fn ignores_data_without_blocks() {
+        assert_eq!(recover_blocks(&b"no blocks here"[..]).count(), 0);
+    }
+}
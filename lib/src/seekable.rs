@@ -0,0 +1,352 @@
+//! Random access to the uncompressed content of bzip2 files.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+use crate::bitwise::bitreader::BitReaderImpl;
+use crate::block::block_decoder::read_block;
+use crate::index::{BlockIndex, IndexedBlock};
+use crate::stream::{what_next, BlockType, Level, StreamWalker};
+use crate::Error;
+
+const DEFAULT_CACHE_SIZE: usize = 4;
+
+/// A reader over the uncompressed content of a bzip2 file which supports seeking.
+///
+/// Only the block containing the current position is decoded, starting at its offset in the
+/// compressed input as recorded in the [BlockIndex]. The most recently used blocks are kept in
+/// a cache, 4 by default.
+pub struct SeekableBz2Reader<R: Read + Seek> {
+    reader: R,
+    index: BlockIndex,
+    position: u64,
+    /// Decoded blocks by index, most recently used first.
+    cache: VecDeque<(usize, Vec<u8>)>,
+    cache_size: usize,
+}
+
+impl<R: Read + Seek> SeekableBz2Reader<R> {
+    /// Create a reader using an index of the input, e.g. read from its sidecar file.
+    pub fn new(reader: R, index: BlockIndex) -> Self {
+        SeekableBz2Reader {
+            reader,
+            index,
+            position: 0,
+            cache: VecDeque::new(),
+            cache_size: DEFAULT_CACHE_SIZE,
+        }
+    }
+
+    /// Create a reader by decoding the whole input once to build the index.
+    pub fn scan(mut reader: R) -> Result<Self, Error> {
+        reader.seek(SeekFrom::Start(0))?;
+        let index = BlockIndex::scan(BufReader::new(&mut reader), 0)?;
+        Ok(Self::new(reader, index))
+    }
+
+    /// Set the number of decoded blocks kept in memory, at least one.
+    pub fn set_cache_size(&mut self, blocks: usize) {
+        self.cache_size = blocks.max(1);
+        self.cache.truncate(self.cache_size);
+    }
+
+    pub fn index(&self) -> &BlockIndex {
+        &self.index
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the decoded data of a block, decoding it if it is not cached.
+    fn block(&mut self, number: usize) -> Result<&[u8], Error> {
+        match self.cache.iter().position(|(cached, _)| *cached == number) {
+            Some(position) => {
+                let entry = self.cache.remove(position).unwrap();
+                self.cache.push_front(entry);
+            }
+            None => {
+                let block = self.index.blocks()[number];
+                let data = self
+                    .decode_block(&block)
+                    .map_err(|error| error.in_block(number))?;
+                self.cache.truncate(self.cache_size - 1);
+                self.cache.push_front((number, data));
+            }
+        }
+        Ok(&self.cache[0].1)
+    }
+
+    fn decode_block(&mut self, block: &IndexedBlock) -> Result<Vec<u8>, Error> {
+        self.reader.seek(SeekFrom::Start(block.bit_offset / 8))?;
+        let mut bit_reader = BitReaderImpl::from_reader(BufReader::new(&mut self.reader));
+        bit_reader.restart_at(block.bit_offset)?;
+        if what_next(&mut bit_reader)? != BlockType::BlockHeader {
+            return Err(Error::BadBlockHeader {
+                block: 0,
+                bit_offset: block.bit_offset,
+            });
+        }
+        // the index does not store the level, so only the largest block length can be enforced
+        let decoded = read_block(&mut bit_reader, Level::best())?.decode(false)?;
+        if let Some(error) = decoded.crc_error() {
+            return Err(error);
+        }
+        if decoded.crc != block.crc || decoded.data.len() as u64 != block.uncompressed_length {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "block index does not match the input",
+            )));
+        }
+        Ok(decoded.data)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableBz2Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let number = match self.index.find(self.position) {
+            Some(number) if !buf.is_empty() => number,
+            _ => return Ok(0),
+        };
+        let offset = (self.position - self.index.blocks()[number].uncompressed_start) as usize;
+        let available = &self.block(number)?[offset..];
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.position += amount as u64;
+        Ok(amount)
+    }
+}
+
+/// Write `length` bytes of the uncompressed data starting at `offset` into the writer and
+/// return the number of bytes written, which is less at the end of the data.
+///
+/// Only the blocks overlapping the range are decoded. Without an index, the uncompressed length
+/// of every block before the range is computed, but its data is neither kept nor checked.
+pub fn extract_range<R: Read + Seek>(
+    mut reader: R,
+    index: Option<BlockIndex>,
+    offset: u64,
+    length: u64,
+    mut writer: impl Write,
+) -> Result<u64, Error> {
+    match index {
+        Some(index) => {
+            let mut reader = SeekableBz2Reader::new(reader, index);
+            reader.set_cache_size(1);
+            reader.seek(SeekFrom::Start(offset))?;
+            Ok(io::copy(&mut reader.take(length), &mut writer)?)
+        }
+        None => {
+            reader.seek(SeekFrom::Start(0))?;
+            extract_unindexed(BufReader::new(reader), offset, length, writer)
+        }
+    }
+}
+
+/// [extract_range] without an index, reading the blocks in order.
+fn extract_unindexed(
+    reader: impl BufRead,
+    offset: u64,
+    length: u64,
+    mut writer: impl Write,
+) -> Result<u64, Error> {
+    let end = offset.saturating_add(length);
+    let mut bit_reader = BitReaderImpl::from_reader(reader);
+    let mut walker = StreamWalker::new(true);
+    let (mut start, mut number, mut written) = (0, 0, 0);
+    while start < end && walker.seek_blocks(&mut bit_reader)? {
+        if what_next(&mut bit_reader)? == BlockType::StreamFooter {
+            walker.read_footer(&mut bit_reader)?;
+            continue;
+        }
+        let block_number = number;
+        let in_block = |error: Error| error.in_block(block_number);
+        number += 1;
+        let block = read_block(&mut bit_reader, walker.level()).map_err(in_block)?;
+        if start < offset {
+            let length = block.decoded_length().map_err(in_block)?;
+            if start + length <= offset {
+                start += length;
+                continue;
+            }
+        }
+        let decoded = block.decode(false).map_err(in_block)?;
+        if let Some(error) = decoded.crc_error() {
+            return Err(in_block(error));
+        }
+        let data = &decoded.data;
+        let from = offset.saturating_sub(start).min(data.len() as u64) as usize;
+        let to = (end - start).min(data.len() as u64) as usize;
+        writer.write_all(&data[from..to])?;
+        written += (to - from) as u64;
+        start += data.len() as u64;
+    }
+    Ok(written)
+}
+
+/// Seeking beyond the end is allowed, reads then return no data.
+impl<R: Read + Seek> Seek for SeekableBz2Reader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.index.uncompressed_size(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        self.position = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::{encode_stream, Level};
+    use crate::test_util::sample_data;
+    use crate::EncodingStrategy;
+    use std::io::Cursor;
+
+    fn compress(data: &[u8]) -> (Vec<u8>, BlockIndex) {
+        let mut compressed = vec![];
+        let index = encode_stream(
+            data,
+            &mut compressed,
+            0,
+            EncodingStrategy::Single,
+            Level::new(1).unwrap(),
+        )
+        .unwrap();
+        (compressed, index)
+    }
+
+    #[test]
+    pub fn reads_at_any_position() {
+        let data = sample_data(250_000);
+        let (compressed, index) = compress(&data);
+        assert_eq!(index.len(), 4);
+        let mut reader = SeekableBz2Reader::new(Cursor::new(compressed), index);
+        let mut buf = vec![0u8; 1000];
+        for position in [159_500, 10, 120_000, 99_999] {
+            reader.seek(SeekFrom::Start(position)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            let position = position as usize;
+            assert_eq!(buf, &data[position..position + 1000]);
+        }
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 249_990);
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[249_990..]);
+        assert!(reader.seek(SeekFrom::Current(-300_000)).is_err());
+    }
+
+    #[test]
+    pub fn scans_input() {
+        let data = sample_data(150_000);
+        let (compressed, _) = compress(&data);
+        let mut reader = SeekableBz2Reader::scan(Cursor::new(compressed)).unwrap();
+        let mut decompressed = vec![];
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    pub fn extracts_range() {
+        let data = sample_data(250_000);
+        let (compressed, index) = compress(&data);
+        for index in [Some(index), None] {
+            let mut extracted = vec![];
+            let written = extract_range(
+                Cursor::new(&compressed),
+                index.clone(),
+                79_000,
+                2000,
+                &mut extracted,
+            )
+            .unwrap();
+            assert_eq!(written, 2000);
+            assert_eq!(extracted, &data[79_000..81_000]);
+
+            let mut extracted = vec![];
+            let written = extract_range(
+                Cursor::new(&compressed),
+                index,
+                249_000,
+                2000,
+                &mut extracted,
+            )
+            .unwrap();
+            assert_eq!(written, 1000);
+            assert_eq!(extracted, &data[249_000..]);
+        }
+    }
+
+    #[test]
+    pub fn extracts_range_of_concatenated_streams() {
+        let data = sample_data(250_000);
+        let (compressed, _) = compress(&data);
+        let concatenated = [&compressed[..], &compressed[..]].concat();
+        let mut extracted = vec![];
+        let written = extract_range(
+            Cursor::new(concatenated),
+            None,
+            240_000,
+            20_000,
+            &mut extracted,
+        )
+        .unwrap();
+        assert_eq!(written, 20_000);
+        assert_eq!(extracted, [&data[240_000..], &data[..10_000]].concat());
+    }
+
+    #[test]
+    pub fn checks_only_blocks_overlapping_range() {
+        let data = sample_data(250_000);
+        let (mut compressed, index) = compress(&data);
+        // the stored CRC of the first block follows its magic
+        let crc_offset = (index.blocks()[0].bit_offset / 8 + 6) as usize;
+        compressed[crc_offset] ^= 1;
+        let mut extracted = vec![];
+        extract_range(Cursor::new(&compressed), None, 150_000, 10, &mut extracted).unwrap();
+        assert_eq!(extracted, &data[150_000..150_010]);
+        assert!(matches!(
+            extract_range(Cursor::new(&compressed), None, 10, 10, io::sink()),
+            Err(Error::BlockCrcMismatch { block: 0, .. })
+        ));
+    }
+
+    #[test]
+    pub fn reads_cached_blocks_without_input() {
+        let data = sample_data(250_000);
+        let (compressed, index) = compress(&data);
+        let mut reader = SeekableBz2Reader::new(Cursor::new(compressed), index);
+        reader.set_cache_size(2);
+        let mut buf = [0u8; 10];
+        for position in [0, 100_000, 200_000] {
+            reader.seek(SeekFrom::Start(position)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+        }
+        reader.get_mut().get_mut().clear();
+
+        reader.seek(SeekFrom::Start(100_010)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[100_010..100_020]);
+        // the first block has been evicted
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        assert!(reader.read_exact(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::{channel, Receiver, SendError, Sender};
+use std::thread;
+
+use crate::bitwise::bitreader::{BitReader, BitReaderImpl};
+use crate::block::block_decoder::{read_block, DecodedBlock, RawBlock};
+use crate::recover::{BLOCK_MAGIC, FOOTER_MAGIC, MAGIC_BITS, MAGIC_MASK};
+use crate::Error;
+
+use super::rewind::RewindableReader;
+use super::walker::StreamWalker;
+use super::{combine_crc, what_next, BlockType};
+
+/// Options for decoding bzip2 streams, see [super::decode_stream_with_options] and
+/// [Bz2Decoder::with_options].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DecodeOptions {
+    /// Continue with the next stream if another one follows the end of a stream, as in files
+    /// produced by pbzip2 or by concatenating `.bz2` files. Anything following the last stream
+    /// which does not start with a stream header is ignored. Enabled by default.
+    pub multi_stream: bool,
+    /// Number of worker threads decoding blocks. The Huffman coded data is always read on the
+    /// calling thread, with `0` (the default) the blocks are decoded there as well.
+    pub num_threads: usize,
+    /// Skip blocks with invalid data or a wrong CRC instead of failing, and continue at the
+    /// next block magic found in the input. Skipped blocks are listed in the [DecodeReport].
+    /// Disabled by default.
+    pub skip_bad_blocks: bool,
+    /// Decode blocks with about 3 instead of 5 bytes of memory per byte of a block at the
+    /// peak, which is slightly slower, like `bzip2 -s`. Disabled by default.
+    pub small_memory: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            multi_stream: true,
+            num_threads: 0,
+            skip_bad_blocks: false,
+            small_memory: false,
+        }
+    }
+}
+
+/// A block skipped because of [DecodeOptions::skip_bad_blocks].
+#[derive(Debug)]
+pub struct DamagedBlock {
+    /// Index of the block, counting across concatenated streams.
+    pub index: usize,
+    /// Offset of the block magic in bits from the start of the compressed input.
+    pub bit_offset: u64,
+    /// Number of uncompressed bytes missing from the output, `None` if the block could not be
+    /// decoded far enough to tell.
+    pub lost_bytes: Option<u64>,
+    /// The error which caused the block to be skipped.
+    pub error: Error,
+}
+
+/// Blocks skipped while decoding, see [Bz2Decoder::report].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct DecodeReport {
+    pub damaged_blocks: Vec<DamagedBlock>,
+}
+
+impl DecodeReport {
+    /// Returns `true` if no block has been skipped.
+    pub fn is_clean(&self) -> bool {
+        self.damaged_blocks.is_empty()
+    }
+}
+
+type DecodeResult = Result<DecodedBlock, Error>;
+
+struct DecodeWorker {
+    send_work: Sender<RawBlock>,
+    receive_result: Receiver<DecodeResult>,
+}
+
+impl DecodeWorker {
+    fn spawn(name: &str, small_memory: bool) -> Self {
+        let (send_work, receive_work) = channel::<RawBlock>();
+        let (send_result, receive_result) = channel::<DecodeResult>();
+        let builder = thread::Builder::new().name(name.into());
+
+        builder
+            .spawn(move || {
+                while let Ok(block) = receive_work.recv() {
+                    // the decoder has been dropped
+                    if send_result.send(block.decode(small_memory)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+        DecodeWorker {
+            send_work,
+            receive_result,
+        }
+    }
+}
+
+/// Read ahead of the blocks handed out by the decoder, in stream order.
+enum Pending {
+    /// Read but not yet decoded, as there are no workers.
+    Read { index: usize, block: RawBlock },
+    /// Handed to the worker with the given index.
+    Dispatched {
+        index: usize,
+        bit_offset: u64,
+        worker: usize,
+    },
+    /// Skipped because its data could not be read.
+    Damaged(DamagedBlock),
+    /// The end of a stream with the stored stream CRC.
+    StreamEnd { stored: u32 },
+    /// Reading failed, nothing follows.
+    Failed(Error),
+}
+
+/// A reader decompressing a bzip2 stream read from an inner reader.
+///
+/// Blocks are decoded lazily, one at a time, when the previously decoded block has been
+/// consumed. Hence at most one uncompressed block is held in memory and reading can be stopped
+/// at any point without decoding the rest of the stream.
+///
+/// With worker threads (see [DecodeOptions::num_threads]) the decoder reads ahead one block per
+/// worker, so that the workers decode in parallel. Blocks are still returned in order.
+///
+/// With [DecodeOptions::skip_bad_blocks] the compressed data of the current block is kept
+/// in memory, so that the input can be rescanned for the next block if it turns out to be
+/// damaged.
+pub struct Bz2Decoder<R: BufRead> {
+    bit_reader: BitReaderImpl<RewindableReader<R>>,
+    buffer: Vec<u8>,
+    position: usize,
+    block_index: usize,
+    stream_crc: u32,
+    walker: StreamWalker,
+    options: DecodeOptions,
+    workers: Vec<DecodeWorker>,
+    next_worker: usize,
+    pending: VecDeque<Pending>,
+    pending_blocks: usize,
+    /// Whether a block of the current stream has been skipped, so the stream CRC is wrong.
+    stream_damaged: bool,
+    report: DecodeReport,
+    /// Offset and CRC of the block returned last by [Bz2Decoder::next_block].
+    last_block: Option<(u64, u32)>,
+}
+
+impl<R: BufRead> Bz2Decoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, DecodeOptions::default())
+    }
+
+    pub fn with_options(reader: R, options: DecodeOptions) -> Self {
+        let workers = (0..options.num_threads)
+            .map(|num| DecodeWorker::spawn(&format!("Decoder {}", num), options.small_memory))
+            .collect::<Vec<_>>();
+        Bz2Decoder {
+            bit_reader: BitReaderImpl::from_reader(RewindableReader::new(
+                reader,
+                options.skip_bad_blocks,
+            )),
+            buffer: vec![],
+            position: 0,
+            block_index: 0,
+            stream_crc: 0,
+            walker: StreamWalker::new(options.multi_stream),
+            options,
+            workers,
+            next_worker: 0,
+            pending: VecDeque::new(),
+            pending_blocks: 0,
+            stream_damaged: false,
+            report: DecodeReport::default(),
+            last_block: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.bit_reader.get_ref().get_ref()
+    }
+
+    /// Reading from the inner reader corrupts the decoder's view of the stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.bit_reader.get_mut().get_mut()
+    }
+
+    /// The blocks skipped so far.
+    pub fn report(&self) -> &DecodeReport {
+        &self.report
+    }
+
+    pub(crate) fn into_report(self) -> DecodeReport {
+        self.report
+    }
+
+    /// Bit offset and CRC of the block returned last by [Bz2Decoder::next_block].
+    pub(crate) fn last_block(&self) -> Option<(u64, u32)> {
+        self.last_block
+    }
+
+    /// Number of compressed bytes consumed so far, including a partially consumed byte.
+    pub(crate) fn total_in(&self) -> u64 {
+        self.bit_reader.position().div_ceil(8)
+    }
+
+    /// Returns the inner reader. Data following the last stream read is left in it, except with
+    /// [DecodeOptions::skip_bad_blocks], which reads ahead.
+    pub fn into_inner(self) -> R {
+        self.bit_reader.into_inner().into_inner()
+    }
+
+    /// Decode the next block and return its content, or `None` at the end of the stream.
+    /// Blocks are counted from the start of the input, across concatenated streams.
+    pub(crate) fn next_block(&mut self) -> Result<Option<&[u8]>, Error> {
+        loop {
+            let capacity = self.workers.len().max(1);
+            while self.pending_blocks < capacity && !self.walker.is_finished() {
+                if let Err(error) = self.read_ahead() {
+                    self.pending.push_back(Pending::Failed(error));
+                    self.walker.finish();
+                }
+            }
+            let (index, result) = match self.pending.pop_front() {
+                None => return Ok(None),
+                Some(Pending::Failed(error)) => return Err(error),
+                Some(Pending::StreamEnd { stored }) => {
+                    if stored != self.stream_crc && !self.stream_damaged {
+                        return Err(Error::StreamCrcMismatch {
+                            stored,
+                            computed: self.stream_crc,
+                        });
+                    }
+                    self.stream_crc = 0;
+                    self.stream_damaged = false;
+                    continue;
+                }
+                Some(Pending::Damaged(damaged)) => {
+                    self.skip(damaged);
+                    continue;
+                }
+                Some(Pending::Read { index, block }) => (
+                    (index, block.block_start),
+                    block.decode(self.options.small_memory),
+                ),
+                Some(Pending::Dispatched {
+                    index,
+                    bit_offset,
+                    worker,
+                }) => (
+                    (index, bit_offset),
+                    self.workers[worker]
+                        .receive_result
+                        .recv()
+                        .unwrap_or_else(|_| Err(worker_failed())),
+                ),
+            };
+            self.pending_blocks -= 1;
+            let (index, bit_offset) = index;
+            let (lost_bytes, error) = match result {
+                Ok(decoded) => match decoded.crc_error() {
+                    None => {
+                        self.last_block = Some((bit_offset, decoded.crc));
+                        self.stream_crc = combine_crc(self.stream_crc, decoded.crc);
+                        self.buffer = decoded.data;
+                        return Ok(Some(&self.buffer));
+                    }
+                    Some(error) => (Some(decoded.data.len() as u64), error),
+                },
+                Err(error) => (None, error),
+            };
+            let error = error.in_block(index);
+            if !self.options.skip_bad_blocks {
+                return Err(error);
+            }
+            self.skip(DamagedBlock {
+                index,
+                bit_offset,
+                lost_bytes,
+                error,
+            });
+        }
+    }
+
+    fn skip(&mut self, damaged: DamagedBlock) {
+        self.stream_damaged = true;
+        self.report.damaged_blocks.push(damaged);
+    }
+
+    /// Read the next block or stream footer from the input and queue it.
+    fn read_ahead(&mut self) -> Result<(), Error> {
+        if !self.walker.seek_blocks(&mut self.bit_reader)? {
+            return Ok(());
+        }
+        let mut block_start = self.bit_reader.position();
+        self.bit_reader.get_mut().mark(block_start / 8);
+        let mut next = what_next(&mut self.bit_reader);
+        loop {
+            let error = match next.and_then(|block_type| self.read_next(block_type)) {
+                Ok(()) => return Ok(()),
+                Err(error) => error.in_block(self.block_index),
+            };
+            if !(self.options.skip_bad_blocks && is_damage(&error)) {
+                return Err(error);
+            }
+            let index = self.block_index;
+            self.block_index += 1;
+            self.pending.push_back(Pending::Damaged(DamagedBlock {
+                index,
+                bit_offset: block_start,
+                lost_bytes: None,
+                error,
+            }));
+            match self.resync(block_start)? {
+                Some(block_type) => {
+                    block_start = self.bit_reader.position() - MAGIC_BITS as u64;
+                    next = Ok(block_type);
+                }
+                None => {
+                    self.walker.finish();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Read the block or stream footer whose magic has just been read and queue it.
+    fn read_next(&mut self, block_type: BlockType) -> Result<(), Error> {
+        match block_type {
+            BlockType::StreamFooter => {
+                let stored = self.walker.read_footer(&mut self.bit_reader)?;
+                self.pending.push_back(Pending::StreamEnd { stored });
+            }
+            BlockType::BlockHeader => {
+                let block = read_block(&mut self.bit_reader, self.walker.level())?;
+                self.dispatch(block);
+            }
+        }
+        Ok(())
+    }
+
+    /// Scan the input for the next block magic or stream footer magic after the start of a
+    /// damaged block, and return which one has been found. Returns `None` at the end of the
+    /// input.
+    fn resync(&mut self, block_start: u64) -> Result<Option<BlockType>, Error> {
+        let start = block_start + 1;
+        self.bit_reader.get_mut().rewind(start / 8);
+        match self.bit_reader.restart_at(start) {
+            Err(error) if is_end_of_input(&error) => return Ok(None),
+            result => result?,
+        }
+        let mut window = 0u64;
+        let mut bits_read = 0;
+        while !self.bit_reader.is_at_end()? {
+            let bit = self.bit_reader.read_u32(1)?;
+            window = ((window << 1) | u64::from(bit)) & MAGIC_MASK;
+            bits_read += 1;
+            match window {
+                _ if bits_read < MAGIC_BITS => {}
+                BLOCK_MAGIC => return Ok(Some(BlockType::BlockHeader)),
+                FOOTER_MAGIC => return Ok(Some(BlockType::StreamFooter)),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Queue a block, handing it to the next worker if there are any. Workers are fed
+    /// round-robin, so their results arrive in the order of the queue.
+    fn dispatch(&mut self, block: RawBlock) {
+        let index = self.block_index;
+        self.block_index += 1;
+        self.pending_blocks += 1;
+        if self.workers.is_empty() {
+            self.pending.push_back(Pending::Read { index, block });
+            return;
+        }
+        let worker = self.next_worker;
+        self.next_worker = (self.next_worker + 1) % self.workers.len();
+        let bit_offset = block.block_start;
+        if let Err(SendError(block)) = self.workers[worker].send_work.send(block) {
+            // decode on the calling thread if the worker is gone
+            self.pending.push_back(Pending::Read { index, block });
+            return;
+        }
+        self.pending.push_back(Pending::Dispatched {
+            index,
+            bit_offset,
+            worker,
+        });
+    }
+}
+
+/// A worker thread terminated without sending the result of a block.
+fn worker_failed() -> Error {
+    Error::Io(io::Error::other("decoder thread terminated unexpectedly"))
+}
+
+fn is_end_of_input(error: &Error) -> bool {
+    matches!(error, Error::Io(error) if error.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+/// Errors caused by damaged blocks, as opposed to failing I/O or a missing stream header. Damaged
+/// data may claim more symbols than there are, and run into the end of the input.
+fn is_damage(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::BadBlockHeader { .. }
+            | Error::InvalidHuffmanTable { .. }
+            | Error::BlockCrcMismatch { .. }
+    ) || is_end_of_input(error)
+}
+
+impl<R: BufRead> Read for Bz2Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.buffer.len() {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            if self.next_block()?.is_none() {
+                return Ok(0);
+            }
+            self.position = 0;
+        }
+        let available = &self.buffer[self.position..];
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.position += amount;
+        Ok(amount)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::{stream_with_overlong_block, Bz2Encoder, Level};
+    use crate::EncodingStrategy;
+    use std::io::Write;
+
+    fn compress(data: &[u8], block_ends: &[usize]) -> Vec<u8> {
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, Level::best());
+        let mut start = 0;
+        for end in block_ends.iter().chain([data.len()].iter()) {
+            encoder.write_all(&data[start..*end]).unwrap();
+            encoder.flush().unwrap();
+            start = *end;
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    pub fn reads_all_blocks() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(10);
+        let compressed = compress(&data, &[100, 200]);
+        let mut decoder = Bz2Decoder::new(&compressed[..]);
+        let mut decompressed = vec![];
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    pub fn stops_early() {
+        let data = b"first block, second block".to_vec();
+        let mut compressed = compress(&data, &[13]);
+        // cut off the stream footer and the end of the second block
+        compressed.truncate(compressed.len() - 20);
+        let mut decoder = Bz2Decoder::new(&compressed[..]);
+        let mut first = [0u8; 13];
+        decoder.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"first block, ");
+        assert!(decoder.read(&mut first).is_err());
+    }
+
+    #[test]
+    pub fn reads_concatenated_streams() {
+        let mut compressed = compress(b"first stream, ", &[]);
+        compressed.append(&mut compress(b"second stream", &[]));
+        compressed.extend_from_slice(b"garbage");
+
+        let mut decompressed = vec![];
+        Bz2Decoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, b"first stream, second stream");
+
+        let options = DecodeOptions {
+            multi_stream: false,
+            ..Default::default()
+        };
+        let mut decompressed = vec![];
+        Bz2Decoder::with_options(&compressed[..], options)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, b"first stream, ");
+    }
+
+    #[test]
+    pub fn decodes_on_worker_threads() {
+        let data = (0..5000).map(|x| (x * x % 251) as u8).collect::<Vec<_>>();
+        let mut compressed = compress(&data, &[1000, 2000, 3000, 4000]);
+        compressed.append(&mut compress(&data, &[2500]));
+        let options = DecodeOptions {
+            num_threads: 3,
+            ..Default::default()
+        };
+        let mut decompressed = vec![];
+        Bz2Decoder::with_options(&compressed[..], options)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data.repeat(2));
+    }
+
+    #[test]
+    pub fn decodes_with_small_memory() {
+        let data = (0..5000).map(|x| (x * x % 251) as u8).collect::<Vec<_>>();
+        let compressed = compress(&data, &[1000, 2000]);
+        let mut randomized = include_bytes!("../../samples/randomized.bz2").to_vec();
+        randomized.extend_from_slice(&compressed);
+        for num_threads in [0, 2] {
+            let options = DecodeOptions {
+                num_threads,
+                small_memory: true,
+                ..Default::default()
+            };
+            let mut decompressed = vec![];
+            Bz2Decoder::with_options(&randomized[..], options)
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            let mut expected = include_bytes!("../../samples/randomized.txt").to_vec();
+            expected.extend_from_slice(&data);
+            assert_eq!(decompressed, expected);
+        }
+    }
+
+    #[test]
+    pub fn reports_errors_after_preceding_blocks() {
+        let data = b"first block, second block, third block".to_vec();
+        let mut compressed = compress(&data, &[13, 27]);
+        compressed.truncate(compressed.len() - 20);
+        let options = DecodeOptions {
+            num_threads: 2,
+            ..Default::default()
+        };
+        let mut decoder = Bz2Decoder::with_options(&compressed[..], options);
+        let mut first = [0u8; 27];
+        decoder.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"first block, second block, ");
+        assert!(decoder.read(&mut first).is_err());
+    }
+
+    fn decode_skipping(compressed: &[u8], num_threads: usize) -> (Vec<u8>, DecodeReport) {
+        let options = DecodeOptions {
+            num_threads,
+            skip_bad_blocks: true,
+            ..Default::default()
+        };
+        let mut decoder = Bz2Decoder::with_options(compressed, options);
+        let mut decompressed = vec![];
+        decoder.read_to_end(&mut decompressed).unwrap();
+        (decompressed, decoder.into_report())
+    }
+
+    #[test]
+    pub fn skips_block_with_wrong_crc() {
+        let data = b"first block, second block, third block".to_vec();
+        let mut compressed = compress(&data, &[13, 27]);
+        let second = crate::recover::recover_blocks(&compressed[..])
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        // flip the lowest bit of the stored CRC
+        let crc_end = second.bit_offset + 48 + 32 - 1;
+        compressed[(crc_end / 8) as usize] ^= 0x80 >> (crc_end % 8);
+        assert!(Bz2Decoder::new(&compressed[..])
+            .read_to_end(&mut vec![])
+            .is_err());
+
+        for num_threads in [0, 2] {
+            let (decompressed, report) = decode_skipping(&compressed, num_threads);
+            assert_eq!(decompressed, b"first block, third block");
+            assert_eq!(report.damaged_blocks.len(), 1);
+            let damaged = &report.damaged_blocks[0];
+            assert_eq!(damaged.index, 1);
+            assert_eq!(damaged.bit_offset, second.bit_offset);
+            assert_eq!(damaged.lost_bytes, Some(14));
+            assert!(matches!(
+                damaged.error,
+                Error::BlockCrcMismatch { block: 1, .. }
+            ));
+        }
+    }
+
+    #[test]
+    pub fn skips_block_with_overlong_run() {
+        let compressed = stream_with_overlong_block(b"first block, ", b"third block");
+        assert!(Bz2Decoder::new(&compressed[..])
+            .read_to_end(&mut vec![])
+            .is_err());
+
+        for num_threads in [0, 2] {
+            let (decompressed, report) = decode_skipping(&compressed, num_threads);
+            assert_eq!(decompressed, b"first block, third block");
+            assert_eq!(report.damaged_blocks.len(), 1);
+            assert!(matches!(
+                report.damaged_blocks[0].error,
+                Error::InvalidHuffmanTable { block: 1, .. }
+            ));
+        }
+    }
+
+    #[test]
+    pub fn resyncs_after_invalid_block() {
+        let data = b"first block, second block, third block".to_vec();
+        let mut compressed = compress(&data, &[13, 27]);
+        compressed.append(&mut compress(b", next stream", &[]));
+        let second = crate::recover::recover_blocks(&compressed[..])
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        // overwrite the code tables and Huffman coded data of the second block
+        let start = ((second.bit_offset + 48 + 32) / 8 + 1) as usize;
+        let end = ((second.bit_offset + second.bit_length) / 8) as usize;
+        compressed[start..end].fill(0xff);
+
+        let (decompressed, report) = decode_skipping(&compressed, 0);
+        assert_eq!(decompressed, b"first block, third block, next stream");
+        assert_eq!(report.damaged_blocks.len(), 1);
+        let damaged = &report.damaged_blocks[0];
+        assert_eq!(damaged.index, 1);
+        assert_eq!(damaged.bit_offset, second.bit_offset);
+        assert_eq!(damaged.lost_bytes, None);
+    }
+
+    #[test]
+    pub fn rejects_invalid_header() {
+        let mut decoder = Bz2Decoder::new(&b"PK\x03\x04"[..]);
+        let error = decoder.read(&mut [0u8; 10]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
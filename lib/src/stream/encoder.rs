@@ -0,0 +1,298 @@
+use std::io::{self, Write};
+
+use crate::bitwise::bitwriter::{BitWriter, BitWriterImpl};
+use crate::bitwise::BitBuffer;
+use crate::block::block_encoder::generate_block_data;
+use crate::block::symbol_statistics::EncodingStrategy;
+use crate::index::BlockIndex;
+
+use super::{
+    combine_crc, write_file_header, write_stream_footer, Level, WorkerThread, FILE_HEADER_BITS,
+};
+
+/// A writer compressing everything written into it as a bzip2 stream.
+///
+/// Input is buffered until a full block is available. Each block is then encoded, either on
+/// the calling thread (if the encoder is created with zero threads) or on a pool of worker
+/// threads which are fed round-robin so the blocks are written in the original order.
+///
+/// The stream footer is only written by [Bz2Encoder::finish], which also returns the inner
+/// writer, or by [Bz2Encoder::try_finish]. Dropping an unfinished encoder finishes the stream
+/// and ignores any errors.
+pub struct Bz2Encoder<W: Write> {
+    bit_writer: Option<BitWriterImpl<W>>,
+    buffer: Vec<u8>,
+    worker_threads: Vec<WorkerThread>,
+    next_worker: usize,
+    total_crc: u32,
+    header_written: bool,
+    footer_written: bool,
+    encoding_strategy: EncodingStrategy,
+    level: Level,
+    index: BlockIndex,
+    /// Bits written so far, excluding the current partial byte.
+    bits_written: u64,
+}
+
+impl<W: Write> Bz2Encoder<W> {
+    /// Create an encoder writing into `writer`. With `num_threads == 0` all blocks are encoded
+    /// on the calling thread. The block size is determined by the compression level.
+    pub fn new(
+        writer: W,
+        num_threads: usize,
+        encoding_strategy: EncodingStrategy,
+        level: Level,
+    ) -> Self {
+        let worker_threads = (0..num_threads)
+            .map(|num| WorkerThread::spawn(&format!("Thread {}", num), encoding_strategy))
+            .collect::<Vec<_>>();
+        Bz2Encoder {
+            bit_writer: Some(BitWriterImpl::from_writer(writer)),
+            buffer: Vec::with_capacity(level.block_size()),
+            worker_threads,
+            next_worker: 0,
+            total_crc: 0,
+            header_written: false,
+            footer_written: false,
+            encoding_strategy,
+            level,
+            index: BlockIndex::default(),
+            bits_written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.bit_writer.as_ref().unwrap().get_ref()
+    }
+
+    /// Writing to the inner writer corrupts the stream unless the encoder is finished.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.bit_writer.as_mut().unwrap().get_mut()
+    }
+
+    /// The blocks written so far. Blocks still being encoded are only included once the
+    /// encoder has been flushed.
+    pub fn index(&self) -> &BlockIndex {
+        &self.index
+    }
+
+    /// Encode all buffered data, write the stream footer and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.bit_writer.take().unwrap().into_inner())
+    }
+
+    /// Encode all buffered data and write the stream footer, keeping the inner writer.
+    /// Further writes fail, finishing again has no effect.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.footer_written {
+            return Ok(());
+        }
+        self.flush_blocks()?;
+        let total_crc = self.total_crc;
+        let bit_writer = self.bit_writer()?;
+        write_stream_footer(&mut *bit_writer, total_crc)?;
+        bit_writer.finalize()?;
+        self.footer_written = true;
+        Ok(self.bit_writer()?.flush()?)
+    }
+
+    /// Returns the bit writer, writing the file header first if nothing has been written yet.
+    fn bit_writer(&mut self) -> io::Result<&mut BitWriterImpl<W>> {
+        let bit_writer = self.bit_writer.as_mut().ok_or_else(finished_error)?;
+        if !self.header_written {
+            write_file_header(&mut *bit_writer, self.level)?;
+            self.bits_written += FILE_HEADER_BITS;
+            self.header_written = true;
+        }
+        Ok(bit_writer)
+    }
+
+    /// Hand the buffered data to the next worker. If this worker is still busy with an older
+    /// block, that block is written first which keeps the blocks in order.
+    fn dispatch_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let work = std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(self.level.block_size()),
+        );
+
+        if self.worker_threads.is_empty() {
+            let (bits, crc) = generate_block_data(&work, self.encoding_strategy);
+            return self.write_block(&bits, work.len(), crc);
+        }
+
+        let index = self.next_worker;
+        self.next_worker = (self.next_worker + 1) % self.worker_threads.len();
+        if self.worker_threads[index].pending {
+            self.flush_worker(index)?;
+        }
+        self.worker_threads[index].send_work(work);
+        Ok(())
+    }
+
+    fn flush_worker(&mut self, index: usize) -> io::Result<()> {
+        let ((bits, crc), length) = self.worker_threads[index].receive_block();
+        self.write_block(&bits, length, crc)
+    }
+
+    fn write_block(
+        &mut self,
+        bits: &BitBuffer,
+        uncompressed_length: usize,
+        crc: u32,
+    ) -> io::Result<()> {
+        self.bit_writer()?.write_buffer(bits)?;
+        self.total_crc = combine_crc(self.total_crc, crc);
+        self.index
+            .push(self.bits_written, uncompressed_length as u64, crc);
+        self.bits_written += bits.bit_length();
+        Ok(())
+    }
+
+    /// Encode the partially filled block and wait for all workers, oldest block first.
+    fn flush_blocks(&mut self) -> io::Result<()> {
+        self.dispatch_block()?;
+        let num_workers = self.worker_threads.len();
+        for offset in 0..num_workers {
+            let index = (self.next_worker + offset) % num_workers;
+            if self.worker_threads[index].pending {
+                self.flush_worker(index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Bz2Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.footer_written {
+            return Err(finished_error());
+        }
+        let block_size = self.level.block_size();
+        let amount = buf.len().min(block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..amount]);
+        if self.buffer.len() == block_size {
+            self.dispatch_block()?;
+        }
+        Ok(amount)
+    }
+
+    /// Terminates the current block early, so that everything written so far can be decoded
+    /// from the output (except for the last partial byte).
+    fn flush(&mut self) -> io::Result<()> {
+        if self.footer_written {
+            return Ok(self.bit_writer()?.flush()?);
+        }
+        self.flush_blocks()?;
+        Ok(self.bit_writer()?.flush()?)
+    }
+}
+
+impl<W: Write> Drop for Bz2Encoder<W> {
+    fn drop(&mut self) {
+        if self.bit_writer.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+fn finished_error() -> io::Error {
+    io::Error::other("encoder already finished")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::decode_stream;
+    use crate::test_util::sample_data;
+
+    fn roundtrip(data: &[u8], num_threads: usize) -> Vec<u8> {
+        let mut encoder =
+            Bz2Encoder::new(vec![], num_threads, EncodingStrategy::Single, Level::best());
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(&compressed[..4], b"BZh9");
+
+        let mut decompressed = vec![];
+        decode_stream(&compressed[..], &mut decompressed, num_threads).unwrap();
+        decompressed
+    }
+
+    #[test]
+    pub fn encodes_empty_input() {
+        assert_eq!(roundtrip(&[], 0), Vec::<u8>::new());
+    }
+
+    #[test]
+    pub fn encodes_on_calling_thread() {
+        let data = sample_data(50_000);
+        assert_eq!(roundtrip(&data, 0), data);
+    }
+
+    #[test]
+    pub fn encodes_multiple_blocks_in_order() {
+        let data = sample_data(2 * Level::best().block_size() + 1234);
+        assert_eq!(roundtrip(&data, 2), data);
+    }
+
+    #[test]
+    pub fn writes_level_to_header() {
+        let level = Level::new(1).unwrap();
+        let data = sample_data(3 * level.block_size() / 2);
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, level);
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(&compressed[..4], b"BZh1");
+
+        let mut decompressed = vec![];
+        decode_stream(&compressed[..], &mut decompressed, 0).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    pub fn flush_ends_block() {
+        let mut encoder = Bz2Encoder::new(vec![], 1, EncodingStrategy::Single, Level::best());
+        encoder.write_all(b"first line\n").unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(b"second line\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decompressed = vec![];
+        decode_stream(&compressed[..], &mut decompressed, 0).unwrap();
+        assert_eq!(decompressed, b"first line\nsecond line\n");
+    }
+
+    #[test]
+    pub fn finishes_once() {
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, Level::best());
+        encoder.write_all(b"finished").unwrap();
+        encoder.try_finish().unwrap();
+        let len = encoder.get_ref().len();
+        encoder.try_finish().unwrap();
+        assert!(encoder.write(b" again").is_err());
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(compressed.len(), len);
+
+        let mut decompressed = vec![];
+        decode_stream(&compressed[..], &mut decompressed, 0).unwrap();
+        assert_eq!(decompressed, b"finished");
+    }
+
+    #[test]
+    pub fn finishes_on_drop() {
+        let mut compressed = vec![];
+        {
+            let mut encoder =
+                Bz2Encoder::new(&mut compressed, 0, EncodingStrategy::Single, Level::best());
+            encoder.write_all(b"dropped").unwrap();
+        }
+        let mut decompressed = vec![];
+        decode_stream(&compressed[..], &mut decompressed, 0).unwrap();
+        assert_eq!(decompressed, b"dropped");
+    }
+}
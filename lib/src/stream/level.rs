@@ -0,0 +1,76 @@
+/// Compression level from 1 to 9 as in bzip2's `-1` .. `-9`.
+///
+/// The level determines the block size of 100k to 900k and is stored in the stream header, so
+/// decoders know the maximum amount of memory needed per block. Smaller blocks compress worse
+/// but need less memory for compression and decompression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Level(u8);
+
+impl Level {
+    /// Returns `None` if the level is not between 1 and 9.
+    pub fn new(level: u8) -> Option<Level> {
+        if (1..=9).contains(&level) {
+            Some(Level(level))
+        } else {
+            None
+        }
+    }
+
+    /// Level 1 with a block size of 100k.
+    pub fn fastest() -> Level {
+        Level(1)
+    }
+
+    /// Level 9 with a block size of 900k.
+    pub fn best() -> Level {
+        Level(9)
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
+    }
+
+    /// The digit following `BZh` in the stream header.
+    pub(crate) fn header_digit(self) -> u8 {
+        b'0' + self.0
+    }
+
+    /// Number of input bytes per block. The block size refers to the data after the initial
+    /// run-length encoding, which can blow up 4 chars to 5, hence we only take 4/5 of it.
+    pub(crate) fn block_size(self) -> usize {
+        usize::from(self.0) * 100_000 * 4 / 5
+    }
+
+    /// Maximum length of a block after the initial run-length encoding, which decoders
+    /// enforce to bound the memory needed for damaged blocks.
+    pub(crate) fn max_block_length(self) -> usize {
+        usize::from(self.0) * 100_000
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::best()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn accepts_levels_1_to_9() {
+        assert_eq!(Level::new(0), None);
+        assert_eq!(Level::new(1), Some(Level::fastest()));
+        assert_eq!(Level::new(9), Some(Level::best()));
+        assert_eq!(Level::new(10), None);
+    }
+
+    #[test]
+    pub fn computes_block_size() {
+        assert_eq!(Level::best().block_size(), 720_000);
+        assert_eq!(Level::fastest().block_size(), 80_000);
+        assert_eq!(Level::best().max_block_length(), 900_000);
+        assert_eq!(Level::new(5).unwrap().header_digit(), b'5');
+    }
+}
@@ -1,8 +1,10 @@
-use crate::bitwise::bitreader::BitReaderImpl;
-use crate::bitwise::bitwriter::convert_to_code_pad_to_byte;
-
-use crate::block::block_encoder::crc_as_bytes;
+mod decoder;
+mod encoder;
+mod level;
+mod rewind;
+mod walker;
 
+use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
 use std::sync::mpsc::channel;
@@ -12,47 +14,47 @@ use std::thread;
 
 use crate::bitwise::bitreader::BitReader;
 use crate::bitwise::bitwriter::BitWriter;
-use crate::bitwise::bitwriter::BitWriterImpl;
-use crate::block::block_decoder::decode_block;
+use crate::bitwise::BitBuffer;
 use crate::block::block_encoder::generate_block_data;
 
-use crate::bitwise::Bit;
-
 use super::block::symbol_statistics::EncodingStrategy;
+use crate::index::BlockIndex;
+use crate::Error;
+
+pub use decoder::{Bz2Decoder, DamagedBlock, DecodeOptions, DecodeReport};
+pub use encoder::Bz2Encoder;
+pub use level::Level;
+pub(crate) use walker::StreamWalker;
+
+pub(crate) fn write_stream_footer(mut bit_writer: impl BitWriter, crc: u32) -> Result<(), Error> {
+    bit_writer.write_u32(0x1772, 16)?;
+    bit_writer.write_u32(0x4538_5090, 32)?;
+    bit_writer.write_u32(crc, 32)
+}
 
-fn stream_footer(crc: u32) -> Vec<Bit> {
-    let mut out = vec![];
-
-    out.append(
-        &mut [0x17, 0x72, 0x45, 0x38, 0x50, 0x90]
-            .iter()
-            .flat_map(|x| convert_to_code_pad_to_byte(*x as u8))
-            .collect::<Vec<_>>(),
-    );
-    let mut crc_as_bits = crc_as_bytes(crc)
-        .iter()
-        .flat_map(|x| convert_to_code_pad_to_byte(*x))
-        .collect::<Vec<_>>();
-    out.append(&mut crc_as_bits);
-    out
+/// Length of the file header in bits.
+pub(crate) const FILE_HEADER_BITS: u64 = 32;
+
+pub(crate) fn write_file_header(mut bit_writer: impl BitWriter, level: Level) -> Result<(), Error> {
+    bit_writer.write_u32(
+        u32::from_be_bytes([b'B', b'Z', b'h', level.header_digit()]),
+        32,
+    )
 }
 
-fn file_header() -> Vec<Bit> {
-    let mut out = vec![];
-    out.append(&mut convert_to_code_pad_to_byte(b'B'));
-    out.append(&mut convert_to_code_pad_to_byte(b'Z'));
-    out.append(&mut convert_to_code_pad_to_byte(b'h'));
-    out.append(&mut convert_to_code_pad_to_byte(b'9'));
-    out
+/// Combine the CRC of the next block into the stream CRC stored in the stream footer.
+pub(crate) fn combine_crc(stream_crc: u32, block_crc: u32) -> u32 {
+    block_crc ^ stream_crc.rotate_left(1)
 }
 
 type Work = Vec<u8>;
-type ComputationResult = (Vec<Bit>, u32);
+type ComputationResult = (BitBuffer, u32);
 
 struct WorkerThread {
     send_work: Sender<Work>,
     receive_result: Receiver<ComputationResult>,
     pending: bool,
+    work_length: usize,
 }
 
 impl WorkerThread {
@@ -74,120 +76,126 @@ impl WorkerThread {
             send_work,
             receive_result,
             pending: false,
+            work_length: 0,
         }
     }
 
-    fn flush_work_buffer(&mut self, mut bit_writer: impl BitWriter, total_crc: &mut u32) {
+    /// Wait for the pending block and return it together with the length of its input.
+    fn receive_block(&mut self) -> (ComputationResult, usize) {
         let result = self.receive_result.recv().unwrap();
-
-        bit_writer.write_bits(&result.0).unwrap();
-        *total_crc = result.1 ^ ((*total_crc << 1) | (*total_crc >> 31));
         self.pending = false;
+        (result, self.work_length)
     }
 
     fn send_work(&mut self, work_to_send: Work) {
         self.pending = true;
+        self.work_length = work_to_send.len();
         self.send_work.send(work_to_send).unwrap();
     }
 }
 
 /// Encode a stream into a writer. Takes a reader and a writer (i.e. two instances of [std::fs::File]).
-/// The number of threads and the encoding strategy can be specified.
+/// The number of threads, the encoding strategy and the compression level can be specified.
+/// Returns the [BlockIndex] of the written stream.
+/// See [Bz2Encoder] for compressing data which is not available as a reader.
 pub fn encode_stream(
     mut read: impl Read,
-    mut writer: impl Write,
+    writer: impl Write,
     num_threads: usize,
     encoding_strategy: EncodingStrategy,
-) {
-    let mut bit_writer = BitWriterImpl::from_writer(&mut writer);
-    // 900_000 * 4 / 5 - RLE can blow up 4chars to 5, hence we keep
-    // a safety margin of 180,000
-    const BLOCK_SIZE: usize = 720_000;
-    let mut total_crc: u32 = 0;
-
-    let mut worker_threads = (0..num_threads)
-        .map(|num| WorkerThread::spawn(&format!("Thread {}", num), encoding_strategy))
-        .collect::<Vec<_>>();
-
-    bit_writer.write_bits(&file_header()).unwrap();
-
-    let mut finalize = false;
-    loop {
-        if finalize {
-            break;
-        }
-        for worker_thread in worker_threads.iter_mut() {
-            let mut buf = vec![];
-            if let Ok(size) = read.by_ref().take(BLOCK_SIZE as u64).read_to_end(&mut buf) {
-                if size == 0 {
-                    finalize = true;
-                    break;
-                }
-            } else {
-                break;
-            }
-            worker_thread.send_work(buf);
-        }
-
-        for worker_thread in worker_threads.iter_mut() {
-            if worker_thread.pending {
-                worker_thread.flush_work_buffer(&mut bit_writer, &mut total_crc);
-            }
-        }
-    }
-
-    bit_writer.write_bits(&stream_footer(total_crc)).unwrap();
-    bit_writer.finalize().unwrap();
+    level: Level,
+) -> Result<BlockIndex, Error> {
+    let mut encoder = Bz2Encoder::new(writer, num_threads, encoding_strategy, level);
+    std::io::copy(&mut read, &mut encoder)?;
+    encoder.try_finish()?;
+    Ok(encoder.index().clone())
 }
 
-fn read_file_header(mut bit_reader: impl BitReader) -> Result<(), ()> {
+/// Read the stream header and return the level stored in it.
+pub(crate) fn read_file_header(mut bit_reader: impl BitReader) -> Result<Level, Error> {
     let res = bit_reader.read_bytes(4)?;
     match &res[..] {
-        [b'B', b'Z', b'h', _] => Ok(()),
-        _ => {
-            println!("Not a valid bz2 file");
-            Err(())
-        }
+        [b'B', b'Z', b'h', digit @ b'1'..=b'9'] => Ok(Level::new(digit - b'0').unwrap()),
+        _ => Err(Error::BadMagic),
     }
 }
 
 #[derive(Debug, PartialEq)]
-enum BlockType {
+pub(crate) enum BlockType {
     StreamFooter,
     BlockHeader,
 }
 
-fn what_next(mut bit_reader: impl BitReader) -> Result<BlockType, ()> {
+pub(crate) fn what_next(mut bit_reader: impl BitReader) -> Result<BlockType, Error> {
+    let bit_offset = bit_reader.position();
     let res = bit_reader.read_bytes(6)?;
     match &res[..] {
         [0x31u8, 0x41u8, 0x59u8, 0x26u8, 0x53u8, 0x59u8] => Ok(BlockType::BlockHeader),
         [0x17, 0x72, 0x45, 0x38, 0x50, 0x90] => Ok(BlockType::StreamFooter),
-        _ => {
-            println!("Expected block start or stream end");
-            Err(())
-        }
+        _ => Err(Error::BadBlockHeader {
+            block: 0,
+            bit_offset,
+        }),
     }
 }
 
 /// Decode a stream into a writer. Takes a reader and a writer (i.e. two instances of [std::fs::File])
-pub fn decode_stream(mut reader: impl Read, mut writer: impl Write) -> Result<(), ()> {
-    let mut bit_reader = BitReaderImpl::from_reader(&mut reader);
-    read_file_header(&mut bit_reader)?;
-    loop {
-        match what_next(&mut bit_reader)? {
-            BlockType::StreamFooter => break,
-            BlockType::BlockHeader => {
-                decode_block(&mut bit_reader, &mut writer)?;
-            }
-        }
+/// Concatenated streams are decoded one after another. With `num_threads > 0` blocks are decoded
+/// in parallel on worker threads, see [DecodeOptions::num_threads].
+/// See [Bz2Decoder] for reading the decompressed data incrementally.
+pub fn decode_stream(
+    reader: impl Read,
+    writer: impl Write,
+    num_threads: usize,
+) -> Result<(), Error> {
+    let options = DecodeOptions {
+        num_threads,
+        ..Default::default()
+    };
+    decode_stream_with_options(reader, writer, options).map(|_| ())
+}
+
+/// Like [decode_stream], but with the given [DecodeOptions]. Returns the blocks skipped with
+/// [DecodeOptions::skip_bad_blocks].
+pub fn decode_stream_with_options(
+    reader: impl Read,
+    mut writer: impl Write,
+    options: DecodeOptions,
+) -> Result<DecodeReport, Error> {
+    let mut decoder = Bz2Decoder::with_options(BufReader::new(reader), options);
+    while let Some(block) = decoder.next_block()? {
+        writer.write_all(block)?;
     }
-    Ok(())
+    Ok(decoder.into_report())
+}
+
+/// A stream with intact blocks holding `first` and `last` and a damaged block between them,
+/// whose data claims a run of zeros too long for any block.
+#[cfg(test)]
+pub(crate) fn stream_with_overlong_block(first: &[u8], last: &[u8]) -> Vec<u8> {
+    use crate::bitwise::bitwriter::BitWriterImpl;
+    use crate::block::block_decoder::write_block_with_run;
+
+    let mut bit_writer = BitWriterImpl::from_writer(vec![]);
+    write_file_header(&mut bit_writer, Level::best()).unwrap();
+    let (first_block, first_crc) = generate_block_data(first, EncodingStrategy::Single);
+    bit_writer.write_buffer(&first_block).unwrap();
+    let mut damaged = BitBuffer::new();
+    write_block_with_run(70, &mut damaged);
+    bit_writer.write_buffer(&damaged).unwrap();
+    let (last_block, last_crc) = generate_block_data(last, EncodingStrategy::Single);
+    bit_writer.write_buffer(&last_block).unwrap();
+    let stream_crc = combine_crc(combine_crc(0, first_crc), last_crc);
+    write_stream_footer(&mut bit_writer, stream_crc).unwrap();
+    bit_writer.finalize().unwrap();
+    bit_writer.into_inner()
 }
 
 #[cfg(test)]
 mod test {
 
     use crate::bitwise::bitreader::BitReaderImpl;
+    use crate::bitwise::bitwriter::BitWriterImpl;
 
     use super::*;
     use std::io::Cursor;
@@ -219,6 +227,92 @@ mod test {
         assert_eq!(BlockType::StreamFooter, what_next(&mut bit_reader).unwrap());
     }
 
+    #[test]
+    pub fn rejects_invalid_header() {
+        let input = b"BZh0";
+        let mut cursor = Cursor::new(input);
+        let mut bit_reader = BitReaderImpl::from_reader(&mut cursor);
+        assert!(matches!(
+            read_file_header(&mut bit_reader),
+            Err(Error::BadMagic)
+        ));
+    }
+
+    #[test]
+    pub fn reports_corrupt_block() {
+        let mut compressed = vec![];
+        encode_stream(
+            &b"If Peter Piper picked a peck of pickled peppers"[..],
+            &mut compressed,
+            1,
+            EncodingStrategy::Single,
+            Level::best(),
+        )
+        .unwrap();
+        // flip a bit of the stored block CRC
+        compressed[10] ^= 1;
+        let result = decode_stream(&compressed[..], vec![], 0);
+        assert!(matches!(
+            result,
+            Err(Error::BlockCrcMismatch {
+                block: 0,
+                bit_offset: 32,
+                ..
+            })
+        ));
+    }
+
+    /// Build a stream from the given blocks, storing the stream CRC of `footer_blocks`.
+    fn stream_of_blocks(blocks: &[&[u8]], footer_blocks: &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut bit_writer = BitWriterImpl::from_writer(&mut out);
+        write_file_header(&mut bit_writer, Level::best()).unwrap();
+        for block in blocks {
+            let (bits, _) = generate_block_data(block, EncodingStrategy::Single);
+            bit_writer.write_buffer(&bits).unwrap();
+        }
+        let stream_crc = footer_blocks.iter().fold(0, |stream_crc, block| {
+            combine_crc(
+                stream_crc,
+                generate_block_data(block, EncodingStrategy::Single).1,
+            )
+        });
+        write_stream_footer(&mut bit_writer, stream_crc).unwrap();
+        bit_writer.finalize().unwrap();
+        out
+    }
+
+    #[test]
+    pub fn verifies_stream_crc() {
+        let first: &[u8] = b"first block ";
+        let second: &[u8] = b"second block";
+        let compressed = stream_of_blocks(&[first, second], &[first, second]);
+        let mut decompressed = vec![];
+        decode_stream(&compressed[..], &mut decompressed, 0).unwrap();
+        assert_eq!(decompressed, b"first block second block");
+    }
+
+    #[test]
+    pub fn detects_reordered_blocks() {
+        let first: &[u8] = b"first block ";
+        let second: &[u8] = b"second block";
+        let swapped = stream_of_blocks(&[second, first], &[first, second]);
+        assert!(matches!(
+            decode_stream(&swapped[..], vec![], 0),
+            Err(Error::StreamCrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    pub fn detects_duplicated_block() {
+        let first: &[u8] = b"first block ";
+        let duplicated = stream_of_blocks(&[first, first], &[first]);
+        assert!(matches!(
+            decode_stream(&duplicated[..], vec![], 0),
+            Err(Error::StreamCrcMismatch { .. })
+        ));
+    }
+
     #[test]
     pub fn detects_error() {
         let data = vec![0, 1, 2, 3, 4, 5];
@@ -0,0 +1,104 @@
+use std::io::{self, BufRead, Read};
+
+/// A reader which can go back to any byte after the last mark, used to rescan damaged blocks.
+/// If recording is disabled, it just passes reads through to the inner reader.
+pub(super) struct RewindableReader<R> {
+    reader: R,
+    recording: bool,
+    /// Bytes read since the mark and the offset of its first byte in the input.
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    /// Offset of the next byte returned.
+    position: u64,
+}
+
+impl<R: BufRead> RewindableReader<R> {
+    pub(super) fn new(reader: R, recording: bool) -> Self {
+        RewindableReader {
+            reader,
+            recording,
+            buffer: vec![],
+            buffer_start: 0,
+            position: 0,
+        }
+    }
+
+    pub(super) fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub(super) fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub(super) fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Forget all bytes before `offset`, which must not be after the current position.
+    pub(super) fn mark(&mut self, offset: u64) {
+        let drop = offset.saturating_sub(self.buffer_start) as usize;
+        self.buffer.drain(..drop.min(self.buffer.len()));
+        self.buffer_start += drop as u64;
+    }
+
+    /// Continue reading at `offset`, which must not be before the last mark.
+    pub(super) fn rewind(&mut self, offset: u64) {
+        debug_assert!(self.recording && offset >= self.buffer_start);
+        self.position = offset;
+    }
+}
+
+impl<R: BufRead> Read for RewindableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.consume(amount);
+        Ok(amount)
+    }
+}
+
+impl<R: BufRead> BufRead for RewindableReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.recording {
+            return self.reader.fill_buf();
+        }
+        let buffered = (self.position - self.buffer_start) as usize;
+        if buffered >= self.buffer.len() {
+            let available = self.reader.fill_buf()?;
+            let amount = available.len();
+            self.buffer.extend_from_slice(available);
+            self.reader.consume(amount);
+        }
+        Ok(&self.buffer[buffered..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if self.recording {
+            self.position += amount as u64;
+        } else {
+            self.reader.consume(amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn rewinds_to_marked_bytes() {
+        let mut reader = RewindableReader::new(&b"abcdef"[..], true);
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        reader.mark(2);
+        reader.rewind(3);
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"def");
+        reader.rewind(2);
+        reader.read_exact(&mut buf[..2]).unwrap();
+        assert_eq!(&buf[..2], b"cd");
+    }
+}
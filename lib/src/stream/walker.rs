@@ -0,0 +1,165 @@
+use std::io::{self, BufRead};
+
+use crate::bitwise::bitreader::{BitReader, BitReaderImpl};
+use crate::Error;
+
+use super::{read_file_header, Level};
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Header,
+    Blocks,
+    NextStream,
+    Finished,
+}
+
+/// Follows the stream headers and footers of the input for [Bz2Decoder](super::Bz2Decoder) and
+/// [inspect](crate::inspect::inspect), which read the blocks in between.
+///
+/// The first stream has to start with a valid header. After a stream footer, the input ends at
+/// the end of the reader, before trailing garbage or before an incomplete header.
+pub(crate) struct StreamWalker {
+    state: State,
+    level: Level,
+    multi_stream: bool,
+}
+
+impl StreamWalker {
+    /// With `multi_stream` false, the input ends after the first stream.
+    pub(crate) fn new(multi_stream: bool) -> Self {
+        StreamWalker {
+            state: State::Header,
+            level: Level::best(),
+            multi_stream,
+        }
+    }
+
+    /// Read the header of the next stream if a stream has ended. Returns `false` at the end of
+    /// the input, otherwise a block magic or a stream footer magic follows.
+    pub(crate) fn seek_blocks<T: BufRead>(
+        &mut self,
+        bit_reader: &mut BitReaderImpl<T>,
+    ) -> Result<bool, Error> {
+        loop {
+            match self.state {
+                State::Finished => return Ok(false),
+                State::Blocks => return Ok(true),
+                State::Header => {
+                    self.level = read_file_header(&mut *bit_reader)?;
+                    self.state = State::Blocks;
+                }
+                State::NextStream => {
+                    self.state = if bit_reader.is_at_end()? {
+                        State::Finished
+                    } else {
+                        match read_file_header(&mut *bit_reader) {
+                            Ok(level) => {
+                                self.level = level;
+                                State::Blocks
+                            }
+                            // trailing garbage
+                            Err(Error::BadMagic) => State::Finished,
+                            Err(Error::Io(error))
+                                if error.kind() == io::ErrorKind::UnexpectedEof =>
+                            {
+                                State::Finished
+                            }
+                            Err(error) => return Err(error),
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    /// Read the stream CRC after a stream footer magic and end the stream. Returns the CRC.
+    pub(crate) fn read_footer<T: BufRead>(
+        &mut self,
+        bit_reader: &mut BitReaderImpl<T>,
+    ) -> Result<u32, Error> {
+        let crc = bit_reader.read_u32(32)?;
+        // streams end at byte boundaries, the padding belongs to the stream
+        bit_reader.align_to_byte();
+        self.state = if self.multi_stream {
+            State::NextStream
+        } else {
+            State::Finished
+        };
+        Ok(crc)
+    }
+
+    /// End the input early, e.g. after an error.
+    pub(crate) fn finish(&mut self) {
+        self.state = State::Finished;
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    /// Level of the current stream, which bounds the length of its blocks.
+    pub(crate) fn level(&self) -> Level {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bitwise::bitwriter::{BitWriter, BitWriterImpl};
+    use crate::stream::{what_next, write_file_header, write_stream_footer, BlockType};
+
+    /// Streams without blocks, followed by `trailing`.
+    fn empty_streams(levels: &[Level], trailing: &[u8]) -> Vec<u8> {
+        let mut bit_writer = BitWriterImpl::from_writer(vec![]);
+        for (crc, level) in levels.iter().enumerate() {
+            write_file_header(&mut bit_writer, *level).unwrap();
+            write_stream_footer(&mut bit_writer, crc as u32).unwrap();
+            bit_writer.finalize().unwrap();
+        }
+        let mut input = bit_writer.into_inner();
+        input.extend_from_slice(trailing);
+        input
+    }
+
+    /// The level and CRC of the streams found by the walker.
+    fn walk(input: &[u8], multi_stream: bool) -> Result<Vec<(Level, u32)>, Error> {
+        let mut bit_reader = BitReaderImpl::from_reader(input);
+        let mut walker = StreamWalker::new(multi_stream);
+        let mut streams = vec![];
+        while walker.seek_blocks(&mut bit_reader)? {
+            assert_eq!(what_next(&mut bit_reader)?, BlockType::StreamFooter);
+            let crc = walker.read_footer(&mut bit_reader)?;
+            streams.push((walker.level(), crc));
+        }
+        Ok(streams)
+    }
+
+    #[test]
+    pub fn walks_concatenated_streams() {
+        let levels = [Level::best(), Level::fastest()];
+        let expected = vec![(Level::best(), 0), (Level::fastest(), 1)];
+        assert_eq!(walk(&empty_streams(&levels, b""), true).unwrap(), expected);
+        assert_eq!(
+            walk(&empty_streams(&levels, b""), false).unwrap(),
+            expected[..1]
+        );
+    }
+
+    #[test]
+    pub fn ends_before_trailing_garbage() {
+        let levels = [Level::best(), Level::fastest()];
+        for trailing in [&b"garbage"[..], b"BZ", b"BZh0"] {
+            assert_eq!(
+                walk(&empty_streams(&levels, trailing), true).unwrap().len(),
+                2
+            );
+        }
+    }
+
+    #[test]
+    pub fn requires_header_of_first_stream() {
+        assert!(matches!(walk(b"garbage", true), Err(Error::BadMagic)));
+        assert!(matches!(walk(b"", true), Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,22 @@
+//! Input data shared by the tests of several modules.
+
+/// Slowly changing bytes from a small alphabet, which compress well.
+pub(crate) fn sample_data(len: usize) -> Vec<u8> {
+    (0..len).map(|x| ((x * 7) % 251 / 13) as u8).collect()
+}
+
+/// Xorshift pseudo random numbers, the same for every run with a given non-zero seed.
+pub(crate) struct XorShift(u32);
+
+impl XorShift {
+    pub(crate) fn new(seed: u32) -> Self {
+        XorShift(seed)
+    }
+
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
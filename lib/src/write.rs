@@ -0,0 +1,403 @@
+//! Compressing and decompressing writers, mirroring `bzip2::write`.
+
+use std::io::{self, Write};
+
+use crate::bitwise::bitreader::{BitReader, BitReaderImpl};
+use crate::block::block_decoder::read_block;
+use crate::recover::{BLOCK_MAGIC, FOOTER_MAGIC, MAGIC_BITS, MAGIC_MASK};
+use crate::stream::{combine_crc, what_next, BlockType, Bz2Encoder, DecodeOptions, StreamWalker};
+use crate::{Compression, EncodingStrategy, Error};
+
+/// Counts the bytes written into the inner writer.
+struct Counter<W> {
+    writer: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amount = self.writer.write(buf)?;
+        self.count += amount as u64;
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A writer compressing everything written into it into the inner writer.
+///
+/// The stream is finished by [BzEncoder::finish], [BzEncoder::try_finish] or when dropping
+/// the encoder.
+pub struct BzEncoder<W: Write> {
+    encoder: Bz2Encoder<Counter<W>>,
+    total_in: u64,
+}
+
+impl<W: Write> BzEncoder<W> {
+    pub fn new(writer: W, level: Compression) -> Self {
+        let counter = Counter { writer, count: 0 };
+        BzEncoder {
+            encoder: Bz2Encoder::new(counter, 0, EncodingStrategy::Single, level.into()),
+            total_in: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.encoder.get_ref().writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.encoder.get_mut().writer
+    }
+
+    /// Write the rest of the stream, keeping the inner writer.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.encoder.try_finish()
+    }
+
+    /// Write the rest of the stream and return the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        Ok(self.encoder.finish()?.writer)
+    }
+
+    /// Number of uncompressed bytes written into the encoder.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of compressed bytes written into the inner writer.
+    pub fn total_out(&self) -> u64 {
+        self.encoder.get_ref().count
+    }
+}
+
+impl<W: Write> Write for BzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amount = self.encoder.write(buf)?;
+        self.total_in += amount as u64;
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+/// Finds the block and stream footer magics in the compressed input, which tell that the block
+/// before them is complete.
+#[derive(Default)]
+struct MagicScanner {
+    /// Number of bits of the input scanned.
+    scanned: u64,
+    window: u64,
+}
+
+impl MagicScanner {
+    /// Scan the input for a magic starting after the bit offset `after`.
+    fn find_after(&mut self, input: &[u8], after: u64) -> bool {
+        while self.scanned < input.len() as u64 * 8 {
+            let byte = input[(self.scanned / 8) as usize];
+            let bit = (byte >> (7 - self.scanned % 8)) & 1;
+            self.window = ((self.window << 1) | u64::from(bit)) & MAGIC_MASK;
+            self.scanned += 1;
+            if (self.window == BLOCK_MAGIC || self.window == FOOTER_MAGIC)
+                && self.scanned > after + MAGIC_BITS as u64
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The first `bytes` bytes have been dropped from the input.
+    fn drop_bytes(&mut self, bytes: usize) {
+        match self.scanned.checked_sub(bytes as u64 * 8) {
+            Some(scanned) => self.scanned = scanned,
+            None => *self = Self::default(),
+        }
+    }
+}
+
+/// A writer decompressing a single bzip2 stream written into it into the inner writer.
+///
+/// The compressed data is buffered until a block is complete, which is when the magic of the
+/// following block or of the stream footer has been written, and then decoded on the calling
+/// thread. Data following the stream is not consumed. Finishing fails if the stream is
+/// incomplete.
+pub struct BzDecoder<W: Write> {
+    writer: Option<W>,
+    /// Compressed data not decoded yet.
+    input: Vec<u8>,
+    /// Offset in bits into `input` of the next stream header, block or stream footer.
+    position: u64,
+    scanner: MagicScanner,
+    walker: StreamWalker,
+    small_memory: bool,
+    block_index: usize,
+    stream_crc: u32,
+    done: bool,
+    finished: bool,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl<W: Write> BzDecoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, DecodeOptions::default())
+    }
+
+    /// Decode with the given options. Only [DecodeOptions::small_memory] applies, blocks are
+    /// decoded on the calling thread and a single stream is decoded.
+    pub fn with_options(writer: W, options: DecodeOptions) -> Self {
+        BzDecoder {
+            writer: Some(writer),
+            input: vec![],
+            position: 0,
+            scanner: MagicScanner::default(),
+            walker: StreamWalker::new(false),
+            small_memory: options.small_memory,
+            block_index: 0,
+            stream_crc: 0,
+            done: false,
+            finished: false,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.as_mut().unwrap()
+    }
+
+    /// Returns `true` once the end of the stream has been decoded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Signal the end of the input and write the rest of the stream into the inner writer.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.done {
+            self.decode_input(true)?;
+        }
+        self.get_mut().flush()
+    }
+
+    /// Decode the rest of the stream and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    /// Number of compressed bytes consumed by the decoder.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of decompressed bytes written into the inner writer.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode the complete blocks of the input and drop their data from it. With `finishing`,
+    /// no more input follows and the rest of the stream has to be decoded.
+    fn decode_input(&mut self, finishing: bool) -> io::Result<()> {
+        let result = self.decode_blocks(finishing);
+        if result.is_err() || self.finished {
+            self.done = true;
+        }
+        let consumed = (self.position / 8) as usize;
+        self.input.drain(..consumed);
+        self.position -= consumed as u64 * 8;
+        self.scanner.drop_bytes(consumed);
+        Ok(result?)
+    }
+
+    fn decode_blocks(&mut self, finishing: bool) -> Result<(), Error> {
+        // running out of input only means waiting for more before finishing
+        let wait = |error: Error| match error {
+            Error::Io(ref io_error)
+                if !finishing && io_error.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                Ok(())
+            }
+            error => Err(error),
+        };
+        while !self.finished {
+            let start = (self.position / 8) as usize;
+            let mut bit_reader = BitReaderImpl::from_reader(&self.input[start..]);
+            bit_reader.restart_at(self.position % 8)?;
+            let unit_start =
+                |bit_reader: &BitReaderImpl<&[u8]>| start as u64 * 8 + bit_reader.position();
+            match self.walker.seek_blocks(&mut bit_reader) {
+                Ok(_) => self.position = unit_start(&bit_reader),
+                Err(error) => return wait(error),
+            }
+            let block_type = match what_next(&mut bit_reader) {
+                Ok(block_type) => block_type,
+                Err(error) => return wait(error),
+            };
+            match block_type {
+                BlockType::BlockHeader => {
+                    if !finishing && !self.scanner.find_after(&self.input, self.position) {
+                        return Ok(());
+                    }
+                    let index = self.block_index;
+                    let block = match read_block(&mut bit_reader, self.walker.level()) {
+                        Ok(block) => block,
+                        // a magic inside the block's data
+                        Err(error) => match wait(error.in_block(index)) {
+                            Ok(()) => continue,
+                            error => return error,
+                        },
+                    };
+                    let decoded = block
+                        .decode(self.small_memory)
+                        .map_err(|error| error.in_block(index))?;
+                    if let Some(error) = decoded.crc_error() {
+                        return Err(error.in_block(index));
+                    }
+                    self.block_index += 1;
+                    self.stream_crc = combine_crc(self.stream_crc, decoded.crc);
+                    self.writer.as_mut().unwrap().write_all(&decoded.data)?;
+                    self.total_out += decoded.data.len() as u64;
+                }
+                BlockType::StreamFooter => {
+                    let stored = match self.walker.read_footer(&mut bit_reader) {
+                        Ok(stored) => stored,
+                        Err(error) => return wait(error),
+                    };
+                    if stored != self.stream_crc {
+                        return Err(Error::StreamCrcMismatch {
+                            stored,
+                            computed: self.stream_crc,
+                        });
+                    }
+                    self.finished = true;
+                }
+            }
+            self.position = unit_start(&bit_reader);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BzDecoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        self.input.extend_from_slice(buf);
+        self.decode_input(false)?;
+        if self.finished {
+            // the stream has been dropped from the input, everything behind it is left
+            let left = self.input.len();
+            self.input.clear();
+            self.total_in += (buf.len() - left) as u64;
+            return Ok(buf.len() - left);
+        }
+        self.total_in += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for BzDecoder<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::Level;
+
+    #[test]
+    pub fn roundtrips() {
+        let data = b"If Peter Piper picked a peck of pickled peppers".repeat(100);
+        let mut encoder = BzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&data).unwrap();
+        assert_eq!(encoder.total_in(), data.len() as u64);
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(&compressed[..4], b"BZh1");
+
+        let mut decoder = BzDecoder::new(vec![]);
+        for chunk in compressed.chunks(7) {
+            decoder.write_all(chunk).unwrap();
+        }
+        assert!(decoder.is_finished());
+        assert_eq!(decoder.total_in(), compressed.len() as u64);
+        assert_eq!(decoder.total_out(), data.len() as u64);
+        assert_eq!(decoder.finish().unwrap(), data);
+    }
+
+    #[test]
+    pub fn counts_compressed_bytes() {
+        let mut encoder = BzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"counted").unwrap();
+        encoder.try_finish().unwrap();
+        assert_eq!(encoder.total_out(), encoder.get_ref().len() as u64);
+    }
+
+    #[test]
+    pub fn leaves_data_after_stream() {
+        let mut encoder = BzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"stream").unwrap();
+        let mut compressed = encoder.finish().unwrap();
+        let len = compressed.len();
+        compressed.extend_from_slice(b"trailer");
+
+        let mut decoder = BzDecoder::new(vec![]);
+        assert_eq!(decoder.write(&compressed[..10]).unwrap(), 10);
+        assert_eq!(decoder.write(&compressed[10..]).unwrap(), len - 10);
+        assert_eq!(decoder.write(b"more").unwrap(), 0);
+        assert_eq!(decoder.finish().unwrap(), b"stream");
+    }
+
+    #[test]
+    pub fn decodes_blocks_once_complete() {
+        let mut encoder = Bz2Encoder::new(vec![], 0, EncodingStrategy::Single, Level::best());
+        encoder.write_all(b"first block, ").unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(b"second block").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder = BzDecoder::new(vec![]);
+        // everything but the stream CRC, so the footer magic ends the second block
+        for byte in compressed[..compressed.len() - 4].chunks(1) {
+            decoder.write_all(byte).unwrap();
+            if decoder.get_ref().is_empty() {
+                continue;
+            }
+            assert!(decoder.get_ref().starts_with(b"first block, "));
+        }
+        assert_eq!(decoder.get_ref(), b"first block, second block");
+        assert!(!decoder.is_finished());
+        decoder
+            .write_all(&compressed[compressed.len() - 4..])
+            .unwrap();
+        assert!(decoder.is_finished());
+    }
+
+    #[test]
+    pub fn fails_on_incomplete_stream() {
+        let mut encoder = BzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"incomplete").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder = BzDecoder::new(vec![]);
+        decoder.write_all(&compressed[..20]).unwrap();
+        assert!(decoder.try_finish().is_err());
+    }
+}